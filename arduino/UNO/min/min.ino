@@ -1,5 +1,13 @@
+// 网关配置了 min_transport = true 时注释掉下面一行，启用 MIN 传输层（序号、ACK、重发）
 #define NO_TRANSPORT_PROTOCOL
 
+#ifndef NO_TRANSPORT_PROTOCOL
+// UNO 只有 2 KB RAM，缩小发送队列：最多 4 帧、共 256 字节
+#define MAX_PAYLOAD 64
+#define TRANSPORT_FIFO_SIZE_FRAMES_BITS 2
+#define TRANSPORT_FIFO_SIZE_FRAME_DATA_BITS 8
+#endif
+
 #include "min.h"
 #include "min.c"
 
@@ -10,6 +18,13 @@ int HEART_LED=A2;
 
 ////////////////////////////////// CALLBACKS ///////////////////////////////////
 
+#ifndef NO_TRANSPORT_PROTOCOL
+uint32_t min_time_ms(void)
+{
+  return millis();
+}
+#endif
+
 void min_tx_start(uint8_t port){
 
 }
@@ -68,12 +83,22 @@ void loop() {
   if(Serial.available() > 0) {
     buf_len = Serial.readBytes(buf, 32U);
     min_poll(&min_ctx, (uint8_t *)buf, (uint8_t)buf_len);
-  }  
+  }
+#ifndef NO_TRANSPORT_PROTOCOL
+  else {
+    // 启用传输层时没有收到数据也要调用，以便重发和回复 ACK
+    min_poll(&min_ctx, (uint8_t *)buf, 0);
+  }
+#endif
 
   uint32_t now = millis();
   if (now - last_sent > 1000U) {
     n = snprintf((char *)min_payload, sizeof(min_payload), "{\"l\":\"SN-004\",\"t\": 27.45,\"h\": 25.36,\"v\": 3.88,\"e\": 0}");
+#ifdef NO_TRANSPORT_PROTOCOL
     min_send_frame(&min_ctx, 0x33U, min_payload, n);
+#else
+    min_queue_frame(&min_ctx, 0x33U, min_payload, n);
+#endif
     last_sent = now;
   }
 }
@@ -0,0 +1,27 @@
+// 比较每条消息都编译模板（Template）和启动时编译一次（CompiledTemplate）的渲染耗时
+// 运行：cargo bench
+#[macro_use]
+extern crate bencher;
+
+use bencher::Bencher;
+use data_template::{call_result, CompiledTemplate, Template};
+
+// 同 gw.toml 中的 msg.template 和 msg.example
+const TEMPLATE: &str = "{<{l}>: [{\"ts\": <#TS#>,\"values\": {\"temperature\": <{t}>, \"humidity\": <{h}>,\"voltage\": <{v}>,\"status\": <{e}>}}]}";
+const EXAMPLE: &str = "{\"l\":\"SN-001\",\"t\": 27.45,\"h\": 25.36,\"v\": 3.88,\"e\": 0}";
+
+fn render_per_message(bench: &mut Bencher) {
+    let data = json::parse(EXAMPLE).unwrap();
+    bench.iter(|| {
+        Template::new(TEMPLATE).render(&data, |model| model.get_call_result()).unwrap()
+    });
+}
+
+fn render_compiled(bench: &mut Bencher) {
+    let data = json::parse(EXAMPLE).unwrap();
+    let template = CompiledTemplate::new(TEMPLATE).unwrap();
+    bench.iter(|| template.render(&data, call_result).unwrap());
+}
+
+benchmark_group!(benches, render_per_message, render_compiled);
+benchmark_main!(benches);
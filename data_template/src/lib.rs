@@ -1,4 +1,6 @@
 extern crate json;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{Local, SecondsFormat, Utc};
 use regex::Regex;
 
 #[derive(Debug)]
@@ -6,15 +8,47 @@ pub struct Template<'a> {
     template: &'a str,
 }
 
+// 启动时编译一次的数据模板，渲染时按顺序输出各段，不再使用正则表达式
+#[derive(Debug)]
+pub struct CompiledTemplate {
+    source: String,
+    tokens: Vec<Token>,
+}
+
+// 编译后的模板中的一段
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    // 标签和解析出的路径，标签不是合法的路径时只能按顶层的键取值
+    Label(String, Option<Vec<PathSegment>>),
+    Call(Call),
+}
+
+// 调用模型的名称和参数，例如 <#TIME(local, "%Y-%m-%d %H:%M:%S")#>
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<String>,
+    // <# #> 之间的原文
+    text: String,
+}
+
 #[derive(Debug)]
 pub enum Model {
     Value(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Value {
     String(String),
     Number(i64),
+    // 不能用 i64 表示的数字，保留解析出的尾数和指数
+    Float(json::number::Number),
+    Bool(bool),
+    Null,
+    Array(Vec<Value>),
+    // 保持原来的属性顺序
+    Object(Vec<(String, Value)>),
 }
 
 #[derive(Debug)]
@@ -22,14 +56,36 @@ pub enum Error {
     RegexError(regex::Error),
     ParseError,
     CallError,
+    // 数据中没有标签指定的路径，值为从开头到缺失的那一级的路径
+    MissingPath(String),
+}
+
+// 标签路径中的一级：对象的键或数组的下标
+#[derive(Debug, PartialEq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
 }
 
 #[derive(Debug)]
 enum CallType {
     GetTimestamp,
+    GetTime,
+    GetUuid,
     Unknown,
 }
 
+impl CallType {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "TS" => CallType::GetTimestamp,
+            "TIME" => CallType::GetTime,
+            "UUID" => CallType::GetUuid,
+            _ => CallType::Unknown,
+        }
+    }
+}
+
 pub type Models = Vec<Model>;
 
 impl From<regex::Error> for Error {
@@ -38,6 +94,58 @@ impl From<regex::Error> for Error {
     }
 }
 
+impl Value {
+    // 值的 JSON 文本，可以直接替换到模板中
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::String(string) => json::stringify(string.as_str()),
+            Value::Number(num) => num.to_string(),
+            // 按尾数和指数原样输出，不经过 f64，NaN 为 null
+            Value::Float(num) => json::JsonValue::Number(*num).dump(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null => "null".to_string(),
+            Value::Array(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_json()).collect();
+                format!("[{}]", values.join(","))
+            }
+            Value::Object(members) => {
+                let members: Vec<String> = members.iter()
+                    .map(|(key, value)| format!("{}:{}", json::stringify(key.as_str()), value.to_json()))
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }
+        }
+    }
+}
+
+impl From<&json::JsonValue> for Value {
+    fn from(value: &json::JsonValue) -> Self {
+        match value {
+            json::JsonValue::Null => Value::Null,
+            json::JsonValue::Short(_) | json::JsonValue::String(_) => Value::String(value.as_str().unwrap_or("").to_string()),
+            json::JsonValue::Boolean(b) => Value::Bool(*b),
+            json::JsonValue::Number(num) => {
+                // 没有小数部分的整数为 Number，其他为 Float
+                let (positive, mantissa, exponent) = num.as_parts();
+                let integer = if exponent >= 0 && mantissa <= i64::MAX as u64 {
+                    10_i64.checked_pow(exponent as u32).and_then(|scale| (mantissa as i64).checked_mul(scale))
+                } else {
+                    None
+                };
+                match integer {
+                    Some(integer) if positive => Value::Number(integer),
+                    Some(integer) => Value::Number(-integer),
+                    None => Value::Float(*num),
+                }
+            }
+            json::JsonValue::Array(values) => Value::Array(values.iter().map(Value::from).collect()),
+            json::JsonValue::Object(object) => {
+                Value::Object(object.iter().map(|(key, value)| (key.to_string(), Value::from(value))).collect())
+            }
+        }
+    }
+}
+
 impl<'a> Template<'a> {
     pub fn new(template: &'a str) -> Self {
         Template {
@@ -52,10 +160,106 @@ impl<'a> Template<'a> {
     }
     // <# TS #>
     pub fn get_call_models(&self) -> Result<Models, Error> {
-        let re = Regex::new(r"<#\s*([^%>]+)\s*#>")?;
+        let re = Regex::new(r"<#\s*(.+?)\s*#>")?;
         let models: Vec<Model> = re.captures_iter(self.template).map(|caps| Model::Value(caps[0].to_string())).collect();
         Ok(models)
     }
+    // 一次扫描模板生成输出：<{label}> 替换为 data 中对应的值，<#NAME#> 替换为 call 返回的值，都转换为 JSON 文本，
+    // 字符串会被转义。替换进去的值不会再被当作模板解析
+    // 每次都会编译模板，同一个模板渲染多次时使用 CompiledTemplate
+    pub fn render<F>(&self, data: &json::JsonValue, mut call: F) -> Result<String, Error>
+    where
+        F: FnMut(&Model) -> Result<Value, Error>,
+    {
+        CompiledTemplate::new(self.template)?.render(data, |c| call(&Model::Value(format!("<#{}#>", c.text))))
+    }
+}
+
+impl CompiledTemplate {
+    // 将模板切分为文本、标签和调用模型，标签的路径在这里解析
+    pub fn new(template: &str) -> Result<Self, Error> {
+        let re = Regex::new(r"<\{\s*([^%>]+)\s*\}>|<#\s*(.+?)\s*#>")?;
+        let mut tokens = Vec::new();
+        let mut last = 0;
+        for m in re.find_iter(template) {
+            if m.start() > last {
+                tokens.push(Token::Text(template[last..m.start()].to_string()));
+            }
+            let model = Model::Value(m.as_str().to_string());
+            if m.as_str().starts_with("<{") {
+                let label = model.get_label()?;
+                let path = parse_path(&label).ok();
+                tokens.push(Token::Label(label, path));
+            } else {
+                tokens.push(Token::Call(model.get_call()?));
+            }
+            last = m.end();
+        }
+        if last < template.len() {
+            tokens.push(Token::Text(template[last..].to_string()));
+        }
+        Ok(CompiledTemplate {
+            source: template.to_string(),
+            tokens,
+        })
+    }
+
+    // 编译前的模板
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    // 一次生成输出：标签替换为 data 中对应的值，调用模型替换为 call 返回的值，都转换为 JSON 文本，
+    // 字符串会被转义。替换进去的值不会再被当作模板解析
+    pub fn render<F>(&self, data: &json::JsonValue, mut call: F) -> Result<String, Error>
+    where
+        F: FnMut(&Call) -> Result<Value, Error>,
+    {
+        let mut output = String::with_capacity(self.source.len());
+        for token in self.tokens.iter() {
+            match token {
+                Token::Text(text) => output.push_str(text),
+                Token::Label(label, path) => output.push_str(&Value::from(lookup(data, label, path.as_deref())?).to_json()),
+                Token::Call(c) => output.push_str(&call(c)?.to_json()),
+            }
+        }
+        Ok(output)
+    }
+}
+
+// 内置的调用模型：
+// TS、TS(ms) 为自 EPOCH 以来的毫秒数，TS(s) 为秒数；
+// TIME 为 ISO 8601 格式的 UTC 时间，TIME(local) 为本地时间，TIME(utc, "%Y-%m-%d %H:%M:%S") 按指定格式输出；
+// UUID 为随机生成的 UUID
+pub fn call_result(call: &Call) -> Result<Value, Error> {
+    let args: Vec<&str> = call.args.iter().map(|arg| arg.as_str()).collect();
+    match (CallType::from_name(&call.name), args.as_slice()) {
+        (CallType::GetTimestamp, []) | (CallType::GetTimestamp, ["ms"]) => Ok(Value::Number(timestamp_msec())),
+        (CallType::GetTimestamp, ["s"]) => Ok(Value::Number(timestamp_msec() / 1000)),
+        (CallType::GetTime, []) => format_time("utc", None),
+        (CallType::GetTime, [zone]) => format_time(zone, None),
+        (CallType::GetTime, [zone, format]) => format_time(zone, Some(format)),
+        (CallType::GetUuid, []) => Ok(Value::String(uuid::Uuid::new_v4().to_hyphenated().to_string())),
+        _ => Err(Error::CallError),
+    }
+}
+
+// 没有指定格式时 UTC 时间为 2020-08-09T09:25:53.255Z，本地时间为 2020-08-09T17:25:53.255+08:00
+fn format_time(zone: &str, format: Option<&str>) -> Result<Value, Error> {
+    if let Some(format) = format {
+        // 格式不合法时 chrono 输出时会 panic
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return Err(Error::CallError);
+        }
+    }
+    let time = match (zone, format) {
+        ("utc", None) => Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        ("utc", Some(format)) => Utc::now().format(format).to_string(),
+        ("local", None) => Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+        ("local", Some(format)) => Local::now().format(format).to_string(),
+        _ => return Err(Error::CallError),
+    };
+    Ok(Value::String(time))
 }
 
 impl Model {
@@ -74,7 +278,7 @@ impl Model {
     }
 
     pub fn is_call(&self) -> bool {
-        let re = match Regex::new(r"<#\s*([^%>]+)\s*#>") {
+        let re = match Regex::new(r"<#\s*(.+?)\s*#>") {
             Ok(re) => re,
             Err(_err) => return false,
         };
@@ -91,69 +295,243 @@ impl Model {
         if let false = self.is_label() {
             return Err(crate::Error::ParseError);
         }
-        let re = Regex::new(r"[^<\{\}\s%>]+")?;
+        let re = Regex::new(r"^<\{\s*([^%>]+?)\s*\}>$")?;
         let model = match self {
             Model::Value(model) => model,
         };
         let label = match re.captures(&model) {
             Some(cap) => {
-                cap[0].to_string()
+                cap[1].to_string()
             },
             None => "".to_string(),
         };
         Ok(label)
     }
 
-    fn get_call_type(&self) -> Result<crate::CallType, Error> {
+    // 标签的路径，例如 sensors.temp[0].value、meta["device-id"]
+    pub fn get_label_path(&self) -> Result<Vec<PathSegment>, Error> {
+        parse_path(&self.get_label()?)
+    }
+
+    // 按标签的路径从数据中取值。为了兼容，顶层有与标签完全相同的键时直接使用该键
+    pub fn get_value<'v>(&self, data: &'v json::JsonValue) -> Result<&'v json::JsonValue, Error> {
+        let label = self.get_label()?;
+        lookup(data, &label, parse_path(&label).ok().as_deref())
+    }
+
+    pub fn get_call_name(&self) -> Result<String, Error> {
+        Ok(self.get_call()?.name)
+    }
+
+    // 调用模型的名称和参数
+    pub fn get_call(&self) -> Result<Call, Error> {
+        let re = Regex::new(r"^<#\s*(.+?)\s*#>$")?;
+        let Model::Value(model) = self;
+        match re.captures(model) {
+            Some(cap) => parse_call(&cap[1]),
+            None => Err(crate::Error::ParseError),
+        }
+    }
+
+    pub fn get_call_result(&self) -> Result<crate::Value, Error> {
         if let false = self.is_call() {
             return Err(crate::Error::ParseError);
         }
-        let re = Regex::new(r"[^<#\}\s#>]+")?;
-        let model = match self {
-            Model::Value(model) => model,
-        };
-        let s = match re.captures(&model) {
-            Some(cap) => {
-                cap[0].to_string()
-            },
-            None => "".to_string(),
+        call_result(&self.get_call()?)
+    }
+}
+
+// NAME 或 NAME(参数, ...)，参数可以用 "" 或 '' 括起来，括起来的参数中可以有逗号、括号，\ 转义其后的字符
+fn parse_call(text: &str) -> Result<Call, Error> {
+    let (name, args) = match text.find('(') {
+        Some(start) => (text[..start].trim(), Some(&text[start + 1..])),
+        None => (text, None),
+    };
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ')') {
+        return Err(crate::Error::ParseError);
+    }
+    let args = match args {
+        Some(args) => parse_args(args)?,
+        None => Vec::new(),
+    };
+    Ok(Call {
+        name: name.to_string(),
+        args,
+        text: text.to_string(),
+    })
+}
+
+// 括号内以逗号分隔的参数，')' 之后不能再有其他字符
+fn parse_args(text: &str) -> Result<Vec<String>, Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while matches!(chars.get(i), Some(c) if c.is_whitespace()) {
+            i += 1;
+        }
+        let mut arg = String::new();
+        let quoted = match chars.get(i) {
+            Some(&quote) if quote == '"' || quote == '\'' => {
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('\\') => {
+                            arg.push(*chars.get(i + 1).ok_or(crate::Error::ParseError)?);
+                            i += 2;
+                        }
+                        Some(&c) if c == quote => break,
+                        Some(&c) => {
+                            arg.push(c);
+                            i += 1;
+                        }
+                        None => return Err(crate::Error::ParseError),
+                    }
+                }
+                i += 1;
+                while matches!(chars.get(i), Some(c) if c.is_whitespace()) {
+                    i += 1;
+                }
+                true
+            }
+            _ => {
+                while i < chars.len() && chars[i] != ',' && chars[i] != ')' {
+                    arg.push(chars[i]);
+                    i += 1;
+                }
+                arg = arg.trim().to_string();
+                false
+            }
         };
-        if s.eq("TS") {
-            Ok(crate::CallType::GetTimestamp)
-        } else {
-            Ok(crate::CallType::Unknown)
+        match chars.get(i) {
+            Some(',') => args.push(arg),
+            Some(')') => {
+                // NAME() 没有参数
+                if quoted || !arg.is_empty() || !args.is_empty() {
+                    args.push(arg);
+                }
+                if i + 1 < chars.len() {
+                    return Err(crate::Error::ParseError);
+                }
+                return Ok(args);
+            }
+            _ => return Err(crate::Error::ParseError),
         }
+        i += 1;
     }
+}
+
+fn timestamp_msec() -> i64 {
+    let n = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(n) => n,
+        Err(_) => std::time::Duration::from_secs(0),
+    };
+    n.as_millis() as i64
+}
 
-    fn get_timestamp_msec(&self) -> Result<i64, Error> {
-        let n = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
-            Ok(n) => n,
-            Err(_) => std::time::Duration::from_secs(0),
+// 为了兼容，顶层有与标签完全相同的键时直接使用该键，否则按路径取值
+fn lookup<'v>(data: &'v json::JsonValue, label: &str, path: Option<&[PathSegment]>) -> Result<&'v json::JsonValue, Error> {
+    if data.has_key(label) {
+        return Ok(&data[label]);
+    }
+    let path = path.ok_or(crate::Error::ParseError)?;
+    let mut value = data;
+    for i in 0..path.len() {
+        let next = match (&path[i], value) {
+            (PathSegment::Key(key), json::JsonValue::Object(object)) => object.get(key),
+            (PathSegment::Index(index), json::JsonValue::Array(array)) => array.get(*index),
+            _ => None,
+        };
+        value = match next {
+            Some(next) => next,
+            None => return Err(crate::Error::MissingPath(path_to_string(&path[..i + 1]))),
         };
-        Ok(n.as_millis() as i64)
     }
+    Ok(value)
+}
 
-    pub fn get_call_result(&self) -> Result<crate::Value, Error> {
-        if let false = self.is_call() {
-            return Err(crate::Error::ParseError);
-        }
-        Regex::new(r"<#\s*([^%>]+)\s*#>")?;
-        match self.get_call_type() {
-            Ok(call_type) => {
-                match call_type {
-                    crate::CallType::GetTimestamp => {
-                        if let Ok(timestamp_msec) = self.get_timestamp_msec() {
-                            return Ok(crate::Value::Number(timestamp_msec));
-                        } else {
-                            return Err(crate::Error::CallError);
+fn parse_path(label: &str) -> Result<Vec<PathSegment>, Error> {
+    let chars: Vec<char> = label.chars().collect();
+    let mut path = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '[' => {
+                i += 1;
+                match chars.get(i) {
+                    Some(&quote) if quote == '"' || quote == '\'' => {
+                        let mut key = String::new();
+                        i += 1;
+                        loop {
+                            match chars.get(i) {
+                                Some('\\') => {
+                                    key.push(*chars.get(i + 1).ok_or(crate::Error::ParseError)?);
+                                    i += 2;
+                                }
+                                Some(&c) if c == quote => break,
+                                Some(&c) => {
+                                    key.push(c);
+                                    i += 1;
+                                }
+                                None => return Err(crate::Error::ParseError),
+                            }
+                        }
+                        i += 1;
+                        path.push(PathSegment::Key(key));
+                    }
+                    _ => {
+                        let start = i;
+                        while i < chars.len() && chars[i] != ']' {
+                            i += 1;
+                        }
+                        let index: String = chars[start..i].iter().collect();
+                        match index.trim().parse() {
+                            Ok(index) => path.push(PathSegment::Index(index)),
+                            Err(_) => return Err(crate::Error::ParseError),
                         }
                     }
-                    _ => Err(crate::Error::CallError),
                 }
-            },
-            Err(err) => Err(err),
+                if chars.get(i) != Some(&']') {
+                    return Err(crate::Error::ParseError);
+                }
+                i += 1;
+            }
+            '.' if !path.is_empty() && i + 1 < chars.len() && chars[i + 1] != '.' && chars[i + 1] != '[' => i += 1,
+            '.' | ']' => return Err(crate::Error::ParseError),
+            _ => {
+                if !path.is_empty() && chars[i - 1] != '.' {
+                    return Err(crate::Error::ParseError);
+                }
+                let start = i;
+                while i < chars.len() && chars[i] != '.' && chars[i] != '[' && chars[i] != ']' {
+                    i += 1;
+                }
+                path.push(PathSegment::Key(chars[start..i].iter().collect()));
+            }
+        }
+    }
+    if path.is_empty() {
+        return Err(crate::Error::ParseError);
+    }
+    Ok(path)
+}
+
+// 路径的文本形式，不能直接写成 .key 的键写成 ["key"]
+fn path_to_string(path: &[PathSegment]) -> String {
+    let mut s = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) if !key.is_empty() && !key.contains(|c| ".[]\"'\\".contains(c)) => {
+                if !s.is_empty() {
+                    s.push('.');
+                }
+                s.push_str(key);
+            }
+            PathSegment::Key(key) => s.push_str(&format!("[{}]", json::stringify(key.as_str()))),
+            PathSegment::Index(index) => s.push_str(&format!("[{}]", index)),
         }
     }
+    s
 }
 
 impl PartialEq for Model {
@@ -237,28 +615,201 @@ mod tests {
         }
     }
 
+    #[test]
+    fn model_get_label_path() {
+        use crate::PathSegment::{Index, Key};
+        let path = crate::Model::Value("<{ sensors.temp[0].value }>".to_string()).get_label_path().unwrap();
+        assert_eq!(path, vec![Key("sensors".to_string()), Key("temp".to_string()), Index(0), Key("value".to_string())]);
+        let path = crate::Model::Value("<{meta[\"device-id\"]}>".to_string()).get_label_path().unwrap();
+        assert_eq!(path, vec![Key("meta".to_string()), Key("device-id".to_string())]);
+        let path = crate::Model::Value("<{['a.b'][1][2]}>".to_string()).get_label_path().unwrap();
+        assert_eq!(path, vec![Key("a.b".to_string()), Index(1), Index(2)]);
+        for label in ["<{a..b}>", "<{a.}>", "<{a[x]}>", "<{a[0}>", "<{a[\"b]}>", "<{a]}>", "<{a[0]b}>"].iter() {
+            match crate::Model::Value(label.to_string()).get_label_path() {
+                Err(crate::Error::ParseError) => {}
+                result => panic!("{} should be a parse error: {:?}", label, result),
+            }
+        }
+    }
+
+    #[test]
+    fn model_get_value() {
+        let data = json::parse(r#"{"t": 1, "a.b": 2, "sensors": {"temp": [{"value": 27.45}]}, "meta": {"device-id": "SN-001"}}"#).unwrap();
+        let value = |label: &str| crate::Model::Value(label.to_string()).get_value(&data).map(|v| v.dump());
+        assert_eq!(value("<{t}>").unwrap(), "1");
+        assert_eq!(value("<{a.b}>").unwrap(), "2");
+        assert_eq!(value("<{sensors.temp[0].value}>").unwrap(), "27.45");
+        assert_eq!(value("<{meta[\"device-id\"]}>").unwrap(), "\"SN-001\"");
+        for (label, missing) in [("<{h}>", "h"), ("<{sensors.temp[1].value}>", "sensors.temp[1]"),
+            ("<{sensors.hum.value}>", "sensors.hum"), ("<{t[0]}>", "t[0]"), ("<{meta[\"id\"]}>", "meta.id"),
+            ("<{meta['a b'].c}>", "meta.a b")].iter() {
+            match value(label) {
+                Err(crate::Error::MissingPath(path)) => assert_eq!(path, *missing),
+                result => panic!("{} should be missing: {:?}", label, result),
+            }
+        }
+    }
+
+    #[test]
+    fn value_from_json() {
+        let data = json::parse(r#"{"s": "a\"b", "i": -3, "f": 27.45, "e": 1.5e-7, "big": 2e3, "b": true, "n": null,
+            "a": [1, 0.5, "x", [false]], "o": {"z": 1, "a": {"k": null}}}"#).unwrap();
+        assert_eq!(crate::Value::from(&data["s"]), crate::Value::String("a\"b".to_string()));
+        assert_eq!(crate::Value::from(&data["i"]), crate::Value::Number(-3));
+        assert_eq!(crate::Value::from(&data["f"]), crate::Value::Float(27.45.into()));
+        assert_eq!(crate::Value::from(&data["big"]), crate::Value::Number(2000));
+        assert_eq!(crate::Value::from(&data["b"]), crate::Value::Bool(true));
+        assert_eq!(crate::Value::from(&data["n"]), crate::Value::Null);
+        for (key, text) in [("s", r#""a\"b""#), ("f", "27.45"), ("e", "0.00000015"), ("b", "true"), ("n", "null"),
+            ("a", r#"[1,0.5,"x",[false]]"#), ("o", r#"{"z":1,"a":{"k":null}}"#)].iter() {
+            assert_eq!(crate::Value::from(&data[*key]).to_json(), *text);
+        }
+        assert_eq!(crate::Value::Float(f64::NAN.into()).to_json(), "null");
+        // 超过 f64 精度或 i64 范围的数字原样输出，json 解析时最多保留 20 位有效数字
+        let data = json::parse("[12345678901234567890, 0.1000000000000000055, -9223372036854775809, 1e400, \
+            0.1000000000000000055511151231257827]").unwrap();
+        let texts = ["12345678901234567890", "1.000000000000000055e-1", "-9223372036854775809", "1e400", "1.0000000000000000555e-1"];
+        for (value, text) in data.members().zip(texts.iter()) {
+            assert_eq!(crate::Value::from(value).to_json(), *text);
+        }
+    }
+
+    #[test]
+    fn template_render() {
+        let template = crate::Template::new("{<{l}>: [{\"ts\": <#TS#>, \"values\": {\"name\": <{n}>, \"t\": <{t}>}}]}");
+        let call = |model: &crate::Model| model.get_call_name().map(crate::Value::String);
+        let data = json::parse(r#"{"l": "SN-001", "n": "probe", "t": 27.45}"#).unwrap();
+        assert_eq!(template.render(&data, call).unwrap(), r#"{"SN-001": [{"ts": "TS", "values": {"name": "probe", "t": 27.45}}]}"#);
+
+        // 设备上报的字符串中有引号、反斜杠、控制字符或模板标记时，输出仍然是合法的 JSON，也不会多出属性
+        let hostile = [
+            "a\"b", "back\\slash\\", "line\nbreak\ttab\u{1}\u{1f}", "x\", \"admin\": true, \"y\": \"",
+            "<{t}>", "<#TS#>", "\u{2028}ünï", "}]}", "",
+        ];
+        for name in hostile.iter() {
+            let mut data = json::JsonValue::new_object();
+            data["l"] = (*name).into();
+            data["n"] = (*name).into();
+            data["t"] = 1.into();
+            let output = template.render(&data, call).unwrap();
+            let parsed = json::parse(&output).unwrap_or_else(|_| panic!("invalid JSON for {:?}: {}", name, output));
+            assert_eq!(parsed.len(), 1);
+            let values = &parsed[*name][0]["values"];
+            assert_eq!(values.len(), 2);
+            assert_eq!(values["name"], *name);
+            assert_eq!(values["t"], 1);
+        }
+
+        match template.render(&json::parse(r#"{"l": "SN-001"}"#).unwrap(), call) {
+            Err(crate::Error::MissingPath(path)) => assert_eq!(path, "n"),
+            result => panic!("n should be missing: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn compiled_template() {
+        let template = crate::CompiledTemplate::new("{\"ts\": <# TS #>, \"t\": <{ sensors.t[0] }>, \"a..b\": <{a..b}>}").unwrap();
+        assert_eq!(template.tokens, vec![
+            crate::Token::Text("{\"ts\": ".to_string()),
+            crate::Token::Call(crate::Model::Value("<#TS#>".to_string()).get_call().unwrap()),
+            crate::Token::Text(", \"t\": ".to_string()),
+            crate::Token::Label("sensors.t[0]".to_string(), Some(vec![
+                crate::PathSegment::Key("sensors".to_string()),
+                crate::PathSegment::Key("t".to_string()),
+                crate::PathSegment::Index(0),
+            ])),
+            crate::Token::Text(", \"a..b\": ".to_string()),
+            crate::Token::Label("a..b".to_string(), None),
+            crate::Token::Text("}".to_string()),
+        ]);
+
+        // 编译后的模板可以反复渲染，不是合法路径的标签仍可按顶层的键取值
+        for t in 0..3 {
+            let data = json::parse(&format!(r#"{{"sensors": {{"t": [{}]}}, "a..b": "x"}}"#, t)).unwrap();
+            let output = template.render(&data, crate::call_result).unwrap();
+            let parsed = json::parse(&output).unwrap();
+            assert!(parsed["ts"].as_i64().unwrap() > 0);
+            assert_eq!(parsed["t"], t);
+            assert_eq!(parsed["a..b"], "x");
+        }
+        assert!(template.render(&json::parse(r#"{"sensors": {"t": [1]}}"#).unwrap(), crate::call_result).is_err());
+        assert!(crate::Model::Value("<#NOT_A_CALL#>".to_string()).get_call_result().is_err());
+        assert_eq!(template.as_str(), "{\"ts\": <# TS #>, \"t\": <{ sensors.t[0] }>, \"a..b\": <{a..b}>}");
+    }
+
     #[test]
     fn model_is_call() {
         assert_eq!(crate::Model::Value("<#TS#>".to_string()).is_call(), true);
     }
 
     #[test]
-    fn model_get_call_type() {
-        match crate::Model::Value("<#TS#>".to_string()).get_call_type(){
-            Ok(call_type) => match call_type {
-                crate::CallType::GetTimestamp => {},
-                _ => panic!("<#TS#> should be CallType::GetTimestamp"),
-            },
-            Err(_) => panic!("<#TS#> should be CallType::GetTimestamp"),
+    fn model_get_call_name() {
+        match crate::Model::Value("<# PEER #>".to_string()).get_call_name() {
+            Ok(name) => assert_eq!(name, "PEER"),
+            Err(_) => panic!("Model::get_call_name test failed"),
         }
     }
 
     #[test]
-    fn model_get_timestamp_msec() {
-        match crate::Model::Value("<#TS#>".to_string()).get_timestamp_msec(){
-            Ok(_) => {},
-            Err(_) => panic!("Model::get_timestamp_msec test failed"),
+    fn model_get_call() {
+        let call = crate::Model::Value(r#"<# TIME(local, "%Y-%m-%d, %H:%M") #>"#.to_string()).get_call().unwrap();
+        assert_eq!(call.name, "TIME");
+        assert_eq!(call.args, vec!["local", "%Y-%m-%d, %H:%M"]);
+        let call = crate::Model::Value(r#"<#F( a b , 'x\'y)', "" )#>"#.to_string()).get_call().unwrap();
+        assert_eq!(call.args, vec!["a b", "x'y)", ""]);
+        assert_eq!(crate::Model::Value("<#UUID()#>".to_string()).get_call().unwrap().args.len(), 0);
+        for bad in ["<#TS(s#>", "<#TS(s) x#>", "<#TS('s#>", "<#T S#>", "<#(s)#>"].iter() {
+            assert!(crate::Model::Value(bad.to_string()).get_call().is_err(), "{} should be invalid", bad);
+        }
+    }
+
+    #[test]
+    fn call_result_builtin() {
+        let result = |call: &str| crate::Model::Value(call.to_string()).get_call_result();
+        match (result("<#TS#>"), result("<#TS(s)#>")) {
+            (Ok(crate::Value::Number(ms)), Ok(crate::Value::Number(s))) => assert!(ms / 1000 - s <= 1),
+            results => panic!("unexpected {:?}", results),
+        }
+        match result("<#TIME#>") {
+            Ok(crate::Value::String(time)) => {
+                assert_eq!(time.len(), "2020-08-09T09:25:53.255Z".len());
+                assert!(time.ends_with('Z'));
+            }
+            time => panic!("unexpected {:?}", time),
+        }
+        match result(r#"<#TIME(utc, "%Y")#>"#) {
+            Ok(crate::Value::String(year)) => assert!(year.parse::<i32>().unwrap() >= 2020),
+            year => panic!("unexpected {:?}", year),
+        }
+        assert!(result("<#TIME(local)#>").is_ok());
+        match (result("<#UUID#>"), result("<#UUID#>")) {
+            (Ok(crate::Value::String(a)), Ok(crate::Value::String(b))) => {
+                assert_eq!(a.len(), 36);
+                assert_ne!(a, b);
+            }
+            uuids => panic!("unexpected {:?}", uuids),
         }
+        for bad in ["<#TS(us)#>", "<#TIME(mars)#>", "<#TIME(utc, \"%Q\")#>", "<#UUID(4)#>"].iter() {
+            assert!(result(bad).is_err(), "{} should fail", bad);
+        }
+    }
+
+    #[test]
+    fn call_type_from_name() {
+        let name = crate::Model::Value("<#TS#>".to_string()).get_call_name().unwrap();
+        match crate::CallType::from_name(&name) {
+            crate::CallType::GetTimestamp => {},
+            _ => panic!("<#TS#> should be CallType::GetTimestamp"),
+        }
+        match crate::CallType::from_name("PEER") {
+            crate::CallType::Unknown => {},
+            _ => panic!("<#PEER#> should be CallType::Unknown"),
+        }
+    }
+
+    #[test]
+    fn timestamp_msec() {
+        assert!(crate::timestamp_msec() > 1596965153255);
     }
 
     #[test]
@@ -0,0 +1,581 @@
+extern crate log;
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use serde_derive::Deserialize;
+use crate::data_source::{DataIfError, DataSource, Health};
+use crate::types::{DataIfConfig, OriginalData};
+
+// linux/can.h、linux/can/raw.h 中的常量
+const AF_CAN: libc::c_int = 29;
+const CAN_RAW: libc::c_int = 1;
+const SOL_CAN_RAW: libc::c_int = 101;
+const CAN_RAW_FILTER: libc::c_int = 1;
+pub const CAN_EFF_FLAG: u32 = 0x8000_0000;
+pub const CAN_RTR_FLAG: u32 = 0x4000_0000;
+pub const CAN_SFF_MASK: u32 = 0x0000_07FF;
+pub const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+pub const CAN_MAX_DLEN: usize = 8;
+
+// 每次读取的最大帧数，避免总线繁忙时一直读取
+const MAX_FRAMES_PER_READ: usize = 1024;
+
+#[repr(C)]
+struct SockaddrCan {
+    can_family: libc::sa_family_t,
+    can_ifindex: libc::c_int,
+    rx_id: u32,
+    tx_id: u32,
+    _pad: u64,
+}
+
+#[repr(C, align(8))]
+struct RawCanFrame {
+    can_id: u32,
+    can_dlc: u8,
+    _pad: u8,
+    _res0: u8,
+    _res1: u8,
+    data: [u8; CAN_MAX_DLEN],
+}
+
+#[repr(C)]
+struct RawCanFilter {
+    can_id: u32,
+    can_mask: u32,
+}
+
+/// CAN 帧（不支持 CAN FD）
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanFrame {
+    pub id: u32,
+    pub extended: bool,
+    pub data: Vec<u8>,
+}
+
+/// 接收过滤器，`id & mask` 与 `frame.id & mask` 相同时接收
+#[derive(Deserialize, Debug, Clone)]
+pub struct CanFilterConfig {
+    pub id: u32,
+    // 默认完全匹配
+    pub mask: Option<u32>,
+    #[serde(default)]
+    pub extended: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    // Intel，DBC 中的 @1
+    #[default]
+    Little,
+    // Motorola，DBC 中的 @0
+    Big,
+}
+
+/// 信号定义，与 DBC 文件中 SG_ 的含义相同
+#[derive(Deserialize, Debug, Clone)]
+pub struct SignalConfig {
+    pub name: String,
+    // Intel 格式为最低位，Motorola 格式为最高位
+    pub start_bit: u16,
+    pub length: u16,
+    #[serde(default)]
+    pub byte_order: Endian,
+    #[serde(default)]
+    pub signed: bool,
+    // 实际值 = 原始值 * scale + offset
+    pub scale: Option<f64>,
+    pub offset: Option<f64>,
+}
+
+impl SignalConfig {
+    /// 信号占用的位，从最高位到最低位。位的编号为 字节序号 * 8 + 字节内的位序号
+    fn bits(&self) -> Vec<usize> {
+        let mut bits = Vec::with_capacity(self.length as usize);
+        let start = self.start_bit as usize;
+        match self.byte_order {
+            Endian::Little => {
+                for i in (0..self.length as usize).rev() {
+                    bits.push(start + i);
+                }
+            }
+            Endian::Big => {
+                let mut bit = start;
+                for _ in 0..self.length {
+                    bits.push(bit);
+                    bit = if bit & 0x07 == 0 { bit + 15 } else { bit - 1 };
+                }
+            }
+        }
+        bits
+    }
+
+    fn is_physical(&self) -> bool {
+        self.scale.is_some() || self.offset.is_some()
+    }
+}
+
+/// 报文定义，对应 DBC 文件中的 BO_
+#[derive(Deserialize, Debug, Clone)]
+pub struct CanMessageConfig {
+    pub id: u32,
+    #[serde(default)]
+    pub extended: bool,
+    // 报文名称，输出为 "message" 字段
+    pub name: Option<String>,
+    // 下行发送时的数据长度，默认 8
+    pub dlc: Option<u8>,
+    #[serde(default)]
+    pub signal: Vec<SignalConfig>,
+}
+
+impl CanMessageConfig {
+    fn dlc(&self) -> usize {
+        self.dlc.unwrap_or(CAN_MAX_DLEN as u8) as usize
+    }
+}
+
+/// 检查信号定义，信号必须在报文的数据范围内
+pub fn check_messages(messages: &[CanMessageConfig]) -> Result<(), String> {
+    for message in messages {
+        let max_id = if message.extended { CAN_EFF_MASK } else { CAN_SFF_MASK };
+        if message.id > max_id {
+            return Err(format!("invalid can id: 0x{:X}", message.id));
+        }
+        if message.dlc() > CAN_MAX_DLEN {
+            return Err(format!("invalid dlc of can message 0x{:X}: {}", message.id, message.dlc()));
+        }
+        for signal in message.signal.iter() {
+            if signal.length == 0 || signal.length > 64 {
+                return Err(format!("invalid length of signal {}: {}", signal.name, signal.length));
+            }
+            if signal.bits().iter().any(|bit| *bit >= message.dlc() * 8) {
+                return Err(format!("signal {} is out of can message 0x{:X}", signal.name, message.id));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 从数据中解码信号，数据不够长时返回 None
+pub fn decode_signal(data: &[u8], signal: &SignalConfig) -> Option<json::JsonValue> {
+    let mut raw: u64 = 0;
+    for bit in signal.bits() {
+        let byte = data.get(bit / 8)?;
+        raw = (raw << 1) | ((byte >> (bit % 8)) & 0x01) as u64;
+    }
+    let length = signal.length as u32;
+    let value: i128 = if signal.signed && length < 64 && raw >> (length - 1) & 0x01 == 1 {
+        raw as i128 - (1_i128 << length)
+    } else if signal.signed {
+        raw as i64 as i128
+    } else {
+        raw as i128
+    };
+    if signal.is_physical() {
+        let value = value as f64 * signal.scale.unwrap_or(1.0) + signal.offset.unwrap_or(0.0);
+        Some(json::JsonValue::from(value))
+    } else if signal.signed {
+        Some(json::JsonValue::from(value as i64))
+    } else {
+        Some(json::JsonValue::from(value as u64))
+    }
+}
+
+/// 将信号的实际值编码到数据中
+pub fn encode_signal(data: &mut [u8], signal: &SignalConfig, value: f64) {
+    let raw = ((value - signal.offset.unwrap_or(0.0)) / signal.scale.unwrap_or(1.0)).round();
+    let raw = if signal.signed { raw as i64 as u64 } else { raw as u64 };
+    let bits = signal.bits();
+    let length = bits.len();
+    for (i, bit) in bits.into_iter().enumerate() {
+        if let Some(byte) = data.get_mut(bit / 8) {
+            if raw >> (length - 1 - i) & 0x01 == 1 {
+                *byte |= 1 << (bit % 8);
+            } else {
+                *byte &= !(1 << (bit % 8));
+            }
+        }
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if hex.len() & 0x01 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+/// 按报文定义解码 CAN 帧。没有定义的报文输出原始数据（十六进制字符串）
+pub fn decode_frame(frame: &CanFrame, messages: &[CanMessageConfig]) -> json::JsonValue {
+    let mut obj = json::JsonValue::new_object();
+    obj["can_id"] = frame.id.into();
+    if frame.extended {
+        obj["extended"] = true.into();
+    }
+    match messages.iter().find(|message| message.id == frame.id && message.extended == frame.extended) {
+        Some(message) => {
+            if let Some(name) = &message.name {
+                obj["message"] = name.as_str().into();
+            }
+            for signal in message.signal.iter() {
+                if let Some(value) = decode_signal(&frame.data, signal) {
+                    obj[signal.name.as_str()] = value;
+                }
+            }
+        }
+        None => obj["data"] = to_hex(&frame.data).into(),
+    }
+    obj
+}
+
+/// 解析下行消息。支持两种格式：
+///
+/// - `{"can_id": 291, "data": "0102030405060708"}`：直接发送原始数据，扩展帧需要 `"extended": true`
+/// - `{"message": "engine_cmd", "signals": {"target_rpm": 1500}}`：按报文定义编码，未给出的信号为 0
+pub fn encode_command(msg: &str, messages: &[CanMessageConfig]) -> Result<CanFrame, String> {
+    let parsed = match json::parse(msg) {
+        Ok(parsed) => parsed,
+        Err(_) => return Err(format!("not a JSON object: {}", msg)),
+    };
+    if let Some(name) = parsed["message"].as_str() {
+        let message = match messages.iter().find(|message| message.name.as_deref() == Some(name)) {
+            Some(message) => message,
+            None => return Err(format!("unknown can message: {}", name)),
+        };
+        let mut data = vec![0_u8; message.dlc()];
+        for (signal_name, value) in parsed["signals"].entries() {
+            let signal = match message.signal.iter().find(|signal| signal.name == signal_name) {
+                Some(signal) => signal,
+                None => return Err(format!("unknown signal {} of can message {}", signal_name, name)),
+            };
+            match value.as_f64() {
+                Some(value) => encode_signal(&mut data, signal, value),
+                None => return Err(format!("invalid value of signal {}: {}", signal_name, value)),
+            }
+        }
+        return Ok(CanFrame {
+            id: message.id,
+            extended: message.extended,
+            data,
+        });
+    }
+    let id = match parsed["can_id"].as_u32() {
+        Some(id) => id,
+        None => return Err(String::from("missing can_id or message")),
+    };
+    let extended = parsed["extended"].as_bool().unwrap_or(false);
+    if id > if extended { CAN_EFF_MASK } else { CAN_SFF_MASK } {
+        return Err(format!("invalid can id: 0x{:X}", id));
+    }
+    let data = match parsed["data"].as_str().and_then(from_hex) {
+        Some(data) if data.len() <= CAN_MAX_DLEN => data,
+        _ => return Err(String::from("data must be a hex string of 0 to 8 bytes")),
+    };
+    Ok(CanFrame {
+        id,
+        extended,
+        data,
+    })
+}
+
+/// CAN_RAW 套接字
+pub struct CanSocket {
+    fd: RawFd,
+}
+
+impl CanSocket {
+    /// 打开 CAN 接口（例如 can0、vcan0），设置接收过滤器，过滤器为空时接收所有帧
+    pub fn open(ifname: &str, filters: &[CanFilterConfig]) -> io::Result<Self> {
+        let name = match CString::new(ifname) {
+            Ok(name) => name,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name")),
+        };
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { libc::socket(AF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, CAN_RAW) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = CanSocket { fd };
+        if !filters.is_empty() {
+            socket.set_filters(filters)?;
+        }
+        let addr = SockaddrCan {
+            can_family: AF_CAN as libc::sa_family_t,
+            can_ifindex: index as libc::c_int,
+            rx_id: 0,
+            tx_id: 0,
+            _pad: 0,
+        };
+        let ret = unsafe {
+            libc::bind(fd, &addr as *const SockaddrCan as *const libc::sockaddr, mem::size_of::<SockaddrCan>() as libc::socklen_t)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    fn set_filters(&self, filters: &[CanFilterConfig]) -> io::Result<()> {
+        let filters: Vec<RawCanFilter> = filters.iter().map(|filter| {
+            let (id_mask, flag) = if filter.extended { (CAN_EFF_MASK, CAN_EFF_FLAG) } else { (CAN_SFF_MASK, 0) };
+            RawCanFilter {
+                can_id: (filter.id & id_mask) | flag,
+                // 同时匹配帧格式，不接收远程帧
+                can_mask: (filter.mask.unwrap_or(id_mask) & id_mask) | CAN_EFF_FLAG | CAN_RTR_FLAG,
+            }
+        }).collect();
+        let ret = unsafe {
+            libc::setsockopt(self.fd, SOL_CAN_RAW, CAN_RAW_FILTER, filters.as_ptr() as *const libc::c_void,
+                (mem::size_of::<RawCanFilter>() * filters.len()) as libc::socklen_t)
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// 读取一帧，没有数据时返回 None
+    pub fn read_frame(&self) -> io::Result<Option<CanFrame>> {
+        let mut raw: RawCanFrame = unsafe { mem::zeroed() };
+        let n = unsafe {
+            libc::read(self.fd, &mut raw as *mut RawCanFrame as *mut libc::c_void, mem::size_of::<RawCanFrame>())
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(err);
+        }
+        if n as usize != mem::size_of::<RawCanFrame>() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete can frame"));
+        }
+        let extended = raw.can_id & CAN_EFF_FLAG != 0;
+        let id = if extended { raw.can_id & CAN_EFF_MASK } else { raw.can_id & CAN_SFF_MASK };
+        let len = (raw.can_dlc as usize).min(CAN_MAX_DLEN);
+        Ok(Some(CanFrame {
+            id,
+            extended,
+            data: raw.data[..len].to_vec(),
+        }))
+    }
+
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        let mut raw: RawCanFrame = unsafe { mem::zeroed() };
+        raw.can_id = if frame.extended { (frame.id & CAN_EFF_MASK) | CAN_EFF_FLAG } else { frame.id & CAN_SFF_MASK };
+        raw.can_dlc = frame.data.len().min(CAN_MAX_DLEN) as u8;
+        raw.data[..raw.can_dlc as usize].copy_from_slice(&frame.data[..raw.can_dlc as usize]);
+        let n = unsafe {
+            libc::write(self.fd, &raw as *const RawCanFrame as *const libc::c_void, mem::size_of::<RawCanFrame>())
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CanSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// SocketCAN 数据接口，`if_name` 为 CAN 接口名称。每个接收到的帧输出为一条 JSON 数据
+pub struct SocketCanSource {
+    if_name: String,
+    filters: Vec<CanFilterConfig>,
+    messages: Vec<CanMessageConfig>,
+    socket: Option<CanSocket>,
+    // 最近一次读取的错误，例如接口被关闭
+    error: Option<String>,
+}
+
+impl SocketCanSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let messages = config.can_message.clone().unwrap_or_default();
+        if let Err(err) = check_messages(&messages) {
+            error!("{}: {}", config.if_name, err);
+            return Err(DataIfError::DataIfConfigError(err));
+        }
+        Ok(Box::new(SocketCanSource {
+            if_name: config.if_name.clone(),
+            filters: config.can_filter.clone().unwrap_or_default(),
+            messages,
+            socket: None,
+            error: None,
+        }))
+    }
+}
+
+impl DataSource for SocketCanSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match CanSocket::open(&self.if_name, &self.filters) {
+            Ok(socket) => {
+                info!("can interface {} opened", self.if_name);
+                self.socket = Some(socket);
+                Ok(())
+            }
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        let mut msgs = Vec::new();
+        while msgs.len() < MAX_FRAMES_PER_READ {
+            match socket.read_frame() {
+                Ok(Some(frame)) => {
+                    self.error = None;
+                    msgs.push(OriginalData::new(&decode_frame(&frame, &self.messages).dump()));
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    debug!("read from {} failed: {}", self.if_name, err);
+                    self.error = Some(err.to_string());
+                    return Err(DataIfError::DataIfReadError);
+                }
+            }
+        }
+        Ok(msgs)
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let frame = match encode_command(msg, &self.messages) {
+            Ok(frame) => frame,
+            Err(err) => {
+                warn!("invalid can command: {}", err);
+                return Err(DataIfError::DataIfWriteError);
+            }
+        };
+        match &self.socket {
+            Some(socket) => match socket.write_frame(&frame) {
+                Ok(_) => Ok(()),
+                Err(err) => {
+                    error!("Write can frame to {} failed: {}", self.if_name, err);
+                    Err(DataIfError::DataIfWriteError)
+                }
+            },
+            None => Err(DataIfError::DataIfWriteError),
+        }
+    }
+
+    fn health(&self) -> Health {
+        match (&self.socket, &self.error) {
+            (None, _) => Health::Down(String::from("not opened")),
+            (Some(_), Some(err)) => Health::Down(err.clone()),
+            (Some(_), None) => Health::Up,
+        }
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(10)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::can_if::*;
+
+    fn signal(name: &str, start_bit: u16, length: u16, byte_order: Endian) -> SignalConfig {
+        SignalConfig {
+            name: name.to_string(),
+            start_bit,
+            length,
+            byte_order,
+            signed: false,
+            scale: None,
+            offset: None,
+        }
+    }
+
+    fn engine() -> CanMessageConfig {
+        CanMessageConfig {
+            id: 0x100,
+            extended: false,
+            name: Some(String::from("engine")),
+            dlc: None,
+            signal: vec![
+                signal("rpm", 0, 16, Endian::Little),
+                SignalConfig { signed: true, ..signal("temp", 16, 8, Endian::Little) },
+                SignalConfig { scale: Some(0.1), offset: Some(-40.0), ..signal("oil", 39, 16, Endian::Big) },
+            ],
+        }
+    }
+
+    #[test]
+    fn signal_decode() {
+        let data = [0xDC, 0x05, 0xFE, 0x00, 0x02, 0x58, 0x00, 0x00];
+        let message = engine();
+        assert_eq!(decode_signal(&data, &message.signal[0]).unwrap(), 1500);
+        assert_eq!(decode_signal(&data, &message.signal[1]).unwrap(), -2);
+        // Motorola 格式，起始位为第 4 字节的最高位：0x0258 * 0.1 - 40
+        assert_eq!(decode_signal(&data, &message.signal[2]).unwrap(), 20.0);
+        assert!(decode_signal(&data[..4], &message.signal[2]).is_none());
+
+        let frame = CanFrame { id: 0x100, extended: false, data: data.to_vec() };
+        let obj = decode_frame(&frame, &[message]);
+        assert_eq!(obj["message"], "engine");
+        assert_eq!(obj["rpm"], 1500);
+        let frame = CanFrame { id: 0x123, extended: false, data: vec![0x01, 0xAB] };
+        assert_eq!(decode_frame(&frame, &[]).dump(), "{\"can_id\":291,\"data\":\"01AB\"}");
+    }
+
+    #[test]
+    fn signal_encode() {
+        let messages = vec![engine()];
+        let frame = encode_command("{\"message\": \"engine\", \"signals\": {\"rpm\": 1500, \"temp\": -2, \"oil\": 20}}", &messages).unwrap();
+        assert_eq!(frame.id, 0x100);
+        assert_eq!(frame.data, vec![0xDC, 0x05, 0xFE, 0x00, 0x02, 0x58, 0x00, 0x00]);
+        let frame = encode_command("{\"can_id\": 291, \"data\": \"01AB\"}", &messages).unwrap();
+        assert_eq!(frame, CanFrame { id: 0x123, extended: false, data: vec![0x01, 0xAB] });
+        assert!(encode_command("{\"can_id\": 4096, \"data\": \"01\"}", &messages).is_err());
+        assert!(encode_command("{\"message\": \"engine\", \"signals\": {\"unknown\": 1}}", &messages).is_err());
+    }
+
+    #[test]
+    fn message_check() {
+        assert!(check_messages(&[engine()]).is_ok());
+        let mut message = engine();
+        message.signal.push(signal("overflow", 60, 8, Endian::Little));
+        assert!(check_messages(&[message]).is_err());
+    }
+
+    // 需要 vcan0，默认不运行：
+    // sudo modprobe vcan && sudo ip link add dev vcan0 type vcan && sudo ip link set up vcan0
+    // cargo test vcan_loopback -- --ignored
+    #[test]
+    #[ignore]
+    fn vcan_loopback() {
+        let filters = vec![CanFilterConfig { id: 0x100, mask: Some(0x7F0), extended: false }];
+        let rx = CanSocket::open("vcan0", &filters).unwrap();
+        let tx = CanSocket::open("vcan0", &[]).unwrap();
+        tx.write_frame(&CanFrame { id: 0x200, extended: false, data: vec![0x01] }).unwrap();
+        tx.write_frame(&CanFrame { id: 0x105, extended: false, data: vec![0x02, 0x03] }).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(rx.read_frame().unwrap(), Some(CanFrame { id: 0x105, extended: false, data: vec![0x02, 0x03] }));
+        assert_eq!(rx.read_frame().unwrap(), None);
+    }
+}
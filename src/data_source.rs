@@ -0,0 +1,862 @@
+//! 数据接口抽象。
+//!
+//! 新增数据接口时实现 `DataSource`，提供一个 `DataSourceBuilder` 类型的创建函数，
+//! 然后在 `Registry::default` 中用 `if_type` 及其使用的配置字段注册即可，配置文件中 `[[data_if]]` 的
+//! 其他字段通过 `DataIfConfig` 传给创建函数。
+
+extern crate min_rs as min;
+extern crate log;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io;
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use spidev::{SpiModeFlags, Spidev, SpidevOptions};
+use crate::interface::{line_settings, parse_delimiter, FileIf, HwIf, LineBuffer, LoraConfig, RhHeader, SerialSettings, SpiIf};
+use crate::interface::{reset_radio, Dio0, RegisterAccess, RH_BROADCAST_ADDRESS, RH_HEADER_LEN};
+use crate::socket_if::{TcpServerIf, TcpClientIf, UdpIf};
+use crate::pipe_if::{FifoSource, StdinSource};
+use crate::sysfs_if::SysfsSource;
+use crate::modbus::{ModbusRtuSource, ModbusTcpSource};
+use crate::can_if::SocketCanSource;
+use crate::min_message::{MinRouter, Routed};
+use crate::decoder::{DecodeErrors, PayloadDecoder};
+use crate::types::{DataIfConfig, OriginalData};
+
+#[derive(Debug)]
+pub enum DataIfError {
+    DataIfOpenError,
+    DataIfInitError,
+    DataIfUnknownType,
+    DataIfReadError,
+    DataIfWriteError,
+    DataIfConfigError(String),
+}
+
+impl fmt::Display for DataIfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataIfError::DataIfConfigError(reason) => write!(f, "DataIfConfigError: {}", reason),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// 数据接口状态
+#[derive(Debug, Clone, PartialEq)]
+pub enum Health {
+    Up,
+    Degraded(String),
+    Down(String),
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Health::Up => write!(f, "up"),
+            Health::Degraded(reason) => write!(f, "degraded({})", reason),
+            Health::Down(reason) => write!(f, "down({})", reason),
+        }
+    }
+}
+
+/// 数据接口产生的网关日志事件，由数据接口线程发布到 `pub_log_topic`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEvent {
+    // 事件类型，例如 "modbus_poll_error"
+    pub event: String,
+    pub message: String,
+    // 附加字段，JSON 对象
+    pub fields: json::JsonValue,
+}
+
+/// 数据接口。
+///
+/// 每个数据接口在单独的线程中创建和使用：先调用 `open`，之后循环调用 `write_to`（有下行消息时）
+/// 和 `read`，每次循环间隔 `poll_interval`。
+pub trait DataSource {
+    /// 打开数据接口，失败时网关退出
+    fn open(&mut self) -> Result<(), DataIfError>;
+    /// 读取已收到的原始数据，没有数据时返回空数组，不应长时间阻塞
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError>;
+    /// 发送下行消息
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError>;
+    /// 发送从 MQTT 主题 `topic` 收到的下行消息，需要按主题区分下行消息的数据接口重写该方法
+    fn write_to(&mut self, _topic: &str, msg: &str) -> Result<(), DataIfError> {
+        self.write(msg)
+    }
+    /// 数据接口当前的状态
+    fn health(&self) -> Health;
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(100)
+    }
+    /// 取出上次调用以来产生的日志事件
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        Vec::new()
+    }
+}
+
+pub type DataSourceBuilder = fn(&DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError>;
+
+// 串口参数，serial_port、serial_line、modbus_rtu 共用
+const SERIAL_KEYS: [&str; 6] = ["baud_rate", "data_bits", "parity", "stop_bits", "flow_control", "timeout_ms"];
+
+/// 数据接口注册表，按 `if_type` 查找创建数据接口的函数及该类型支持的配置字段。
+#[derive(Clone)]
+pub struct Registry {
+    builders: HashMap<String, (DataSourceBuilder, Vec<&'static str>)>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            builders: HashMap::new(),
+        }
+    }
+
+    /// `keys` 为该类型使用的 `DataIfConfig` 字段，name、if_name、if_type、template、example 除外
+    pub fn register(&mut self, if_type: &str, builder: DataSourceBuilder, keys: &[&'static str]) {
+        if self.builders.insert(if_type.to_string(), (builder, keys.to_vec())).is_some() {
+            warn!("data interface type {} registered twice", if_type);
+        }
+    }
+
+    /// 检查 `if_type` 是否已注册，以及配置中是否有该类型不支持的字段
+    pub fn check(&self, config: &DataIfConfig) -> Result<(), DataIfError> {
+        let keys = match self.builders.get(&config.if_type) {
+            Some((_, keys)) => keys,
+            None => {
+                error!("DataIf type unknown: {}", config.if_type);
+                return Err(DataIfError::DataIfUnknownType);
+            }
+        };
+        let unsupported: Vec<&str> = config.set_keys().into_iter().filter(|key| !keys.contains(key)).collect();
+        if !unsupported.is_empty() {
+            let err = format!("{} not supported by {}", unsupported.join(", "), config.if_type);
+            error!("{}: {}", config.if_name, err);
+            return Err(DataIfError::DataIfConfigError(err));
+        }
+        Ok(())
+    }
+
+    pub fn build(&self, config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        self.check(config)?;
+        let (builder, _) = &self.builders[&config.if_type];
+        builder(config)
+    }
+}
+
+impl Default for Registry {
+    /// 包含网关内置的所有数据接口
+    fn default() -> Self {
+        let serial = |keys: &[&'static str]| [&SERIAL_KEYS[..], keys].concat();
+        let mut registry = Registry::new();
+        registry.register("text_file", TextFileSource::build, &["offset_file"]);
+        registry.register("serial_port", MinSerialSource::build,
+            &serial(&["min_transport", "min_message", "decoder", "struct_field"]));
+        registry.register("serial_line", SerialLineSource::build, &serial(&["delimiter", "terminator", "max_line_len"]));
+        registry.register("spi_sx1276", Sx1276Source::build,
+            &["lora", "dio0_chip", "dio0_line", "reset_chip", "reset_line", "decoder", "struct_field"]);
+        registry.register("tcp_server", TcpServerIf::build, &["max_connections"]);
+        registry.register("tcp_client", TcpClientIf::build, &[]);
+        registry.register("udp", UdpIf::build, &["decoder", "struct_field"]);
+        registry.register("modbus_rtu", ModbusRtuSource::build,
+            &serial(&["device", "poll_interval_ms", "response_timeout_ms"]));
+        registry.register("modbus_tcp", ModbusTcpSource::build, &["device", "poll_interval_ms", "response_timeout_ms"]);
+        registry.register("socketcan", SocketCanSource::build, &["can_filter", "can_message"]);
+        registry.register("stdin", StdinSource::build, &["delimiter", "max_line_len"]);
+        registry.register("fifo", FifoSource::build, &["delimiter", "max_line_len"]);
+        registry.register("sysfs", SysfsSource::build, &["sensor", "poll_interval_ms"]);
+        registry
+    }
+}
+
+/// 文本文件
+pub struct TextFileSource {
+    if_name: String,
+    file_if: FileIf,
+}
+
+impl TextFileSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let offset_file = match &config.offset_file {
+            Some(offset_file) => offset_file.clone(),
+            None => format!("{}.offset", config.if_name),
+        };
+        Ok(Box::new(TextFileSource {
+            if_name: config.if_name.clone(),
+            file_if: FileIf::new(&config.if_name, &offset_file),
+        }))
+    }
+}
+
+impl DataSource for TextFileSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match File::open(&self.if_name) {
+            Ok(_file) => Ok(()),
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        match self.file_if.read() {
+            Ok(lines) => Ok(lines.iter().map(|line| OriginalData::new(line)).collect()),
+            Err(err) => {
+                debug!("read text file failed: {}", err);
+                Err(DataIfError::DataIfReadError)
+            }
+        }
+    }
+
+    fn write(&mut self, _msg: &str) -> Result<(), DataIfError> {
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        Health::Up
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+/// 使用 MIN 协议的串口
+pub struct MinSerialSource {
+    if_name: String,
+    settings: SerialSettings,
+    // 是否启用 MIN 传输层（序号、ACK、重发），由 min-rs 实现
+    use_transport: bool,
+    min: Option<min::Context<'static, HwIf>>,
+    // MIN ID 与消息类型的映射
+    router: MinRouter,
+    decode_errors: DecodeErrors,
+    // 上次写入日志的传输层计数器
+    reported_stats: MinStats,
+    last_report: Instant,
+    events: Vec<LogEvent>,
+    buf: Vec<u8>,
+}
+
+/// min-rs 传输层的诊断计数器
+#[derive(Debug, Default, Clone, PartialEq)]
+struct MinStats {
+    // 超时或对端 NACK 后重发的帧数
+    retransmitted: u32,
+    // 发送队列已满被丢弃的帧数
+    dropped_frames: u32,
+    // 序号不是期望值而被丢弃的帧数（重复帧或之前有帧丢失）
+    sequence_mismatch_drop: u32,
+    spurious_acks: u32,
+    // 收到对端 RESET（重新同步序号）的次数
+    resets_received: u32,
+}
+
+impl MinStats {
+    fn new(stats: &min::TransportStats) -> Self {
+        MinStats {
+            retransmitted: stats.retransmitted,
+            dropped_frames: stats.dropped_frames,
+            sequence_mismatch_drop: stats.sequence_mismatch_drop,
+            spurious_acks: stats.spurious_acks,
+            resets_received: stats.resets_received,
+        }
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        let mut obj = json::JsonValue::new_object();
+        obj["retransmitted"] = self.retransmitted.into();
+        obj["dropped_frames"] = self.dropped_frames.into();
+        obj["sequence_mismatch_drop"] = self.sequence_mismatch_drop.into();
+        obj["spurious_acks"] = self.spurious_acks.into();
+        obj["resets_received"] = self.resets_received.into();
+        obj
+    }
+}
+
+// 传输层计数器写入日志的最小间隔
+const MIN_STATS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+impl MinSerialSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let settings = match config.serial_settings() {
+            Ok(settings) => settings,
+            Err(err) => {
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+        };
+        let decoder = PayloadDecoder::from_config(config)?.unwrap_or(PayloadDecoder::Json);
+        let router = match MinRouter::new(config.min_message.as_deref().unwrap_or(&[]), decoder) {
+            Ok(router) => router,
+            Err(err) => {
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+        };
+        Ok(Box::new(MinSerialSource {
+            if_name: config.if_name.clone(),
+            settings,
+            use_transport: config.min_transport.unwrap_or(false),
+            min: None,
+            router,
+            decode_errors: DecodeErrors::new(&config.if_name),
+            reported_stats: MinStats::default(),
+            last_report: Instant::now(),
+            events: Vec::new(),
+            buf: vec![0; 255],
+        }))
+    }
+
+    /// 按 MIN ID 处理收到的一帧，数据放入 `msgs`，日志和确认作为日志事件发布
+    fn received(&mut self, min_id: u8, payload: &[u8], msgs: &mut Vec<OriginalData>) {
+        match self.router.route(min_id, payload) {
+            Ok(Routed::Data(data)) => msgs.push(data),
+            Ok(Routed::Log(event)) => {
+                info!("{}: {}: {}", self.if_name, event.event, event.message);
+                self.events.push(event);
+            }
+            Err(err) => self.decode_errors.record(&err),
+        }
+    }
+
+    /// 启用传输层时，计数器有变化则定期写入日志并产生日志事件
+    fn report_stats(&mut self) {
+        let stats = match self.min.as_ref() {
+            Some(min) if self.use_transport => MinStats::new(&min.transport_stats()),
+            _ => return,
+        };
+        if stats == self.reported_stats || self.last_report.elapsed() < MIN_STATS_REPORT_INTERVAL {
+            return;
+        }
+        let message = format!("{}: MIN transport retransmitted {}, dropped {}, sequence mismatch {}, resets received {}",
+            self.if_name, stats.retransmitted, stats.dropped_frames, stats.sequence_mismatch_drop, stats.resets_received);
+        info!("{}", message);
+        self.events.push(LogEvent {
+            event: String::from("min_transport_stats"),
+            message,
+            fields: stats.to_json(),
+        });
+        self.reported_stats = stats;
+        self.last_report = Instant::now();
+    }
+}
+
+impl DataSource for MinSerialSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        let port = match self.settings.open(&self.if_name) {
+            Ok(port) => port,
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                return Err(DataIfError::DataIfOpenError);
+            }
+        };
+        // MIN 上下文引用串口，串口在网关运行期间一直使用，不需要释放
+        let uart: &'static HwIf = Box::leak(Box::new(HwIf::new(port, String::from("uart"), 128)));
+        self.min = Some(min::Context::new(String::from("min"), uart, 0, self.use_transport));
+        if self.use_transport {
+            info!("{}: MIN transport enabled", self.if_name);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let min = match self.min.as_mut() {
+            Some(min) => min,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        // 没有收到数据时也要调用 poll，传输层需要回复 ACK、重发未确认的帧
+        let n = min.hw_if.read(&mut self.buf[..]).unwrap_or(0);
+        min.poll(&self.buf[0..n], n as u32);
+        // 一次读到的数据可能包含多帧
+        let mut frames = Vec::new();
+        while let Ok(msg) = min.get_msg() {
+            frames.push((msg.min_id, msg.buf[0..msg.len as usize].to_vec()));
+        }
+        let mut msgs = Vec::new();
+        for (min_id, payload) in frames {
+            self.received(min_id, &payload, &mut msgs);
+        }
+        self.report_stats();
+        Ok(msgs)
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        self.write_to("", msg)
+    }
+
+    fn write_to(&mut self, topic: &str, msg: &str) -> Result<(), DataIfError> {
+        let min_id = self.router.downlink_id(topic);
+        if msg.len() > min::MAX_PAYLOAD {
+            warn!("{}: downlink of {} bytes exceeds the MIN payload limit of {} bytes", self.if_name, msg.len(), min::MAX_PAYLOAD);
+            return Err(DataIfError::DataIfWriteError);
+        }
+        let min = match self.min.as_mut() {
+            Some(min) => min,
+            None => return Err(DataIfError::DataIfWriteError),
+        };
+        // 启用传输层时放入发送队列，由 poll 等待 ACK、超时重发
+        let result = if self.use_transport {
+            min.queue_frame(min_id, msg.as_bytes(), msg.len() as u8)
+        } else {
+            min.send_frame(min_id, msg.as_bytes(), msg.len() as u8)
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                debug!("write {} failed: {:?}", self.if_name, err);
+                Err(DataIfError::DataIfWriteError)
+            }
+        }
+    }
+
+    fn health(&self) -> Health {
+        match self.min {
+            Some(_) => Health::Up,
+            None => Health::Down(String::from("not opened")),
+        }
+    }
+
+    fn poll_interval(&self) -> Duration {
+        // 传输层按超时重发，需要更频繁地调用 poll
+        if self.use_transport {
+            Duration::from_millis(10)
+        } else {
+            Duration::from_millis(100)
+        }
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        let mut events = std::mem::take(&mut self.events);
+        events.extend(self.decode_errors.take_events());
+        events
+    }
+}
+
+/// 按行收发的串口，不使用 MIN 协议，适用于输出 NMEA、JSON 等文本行的传感器
+pub struct SerialLineSource {
+    if_name: String,
+    settings: SerialSettings,
+    terminator: Vec<u8>,
+    lines: LineBuffer,
+    port: Option<Box<dyn serialport::SerialPort>>,
+    buf: Vec<u8>,
+}
+
+impl SerialLineSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let config_error = |err: String| {
+            error!("{}: {}", config.if_name, err);
+            DataIfError::DataIfConfigError(err)
+        };
+        let settings = config.serial_settings().map_err(config_error)?;
+        let (delimiter, max_line_len) = line_settings(&config.if_type, config.delimiter.as_deref(), config.max_line_len)
+            .map_err(config_error)?;
+        let terminator = parse_delimiter(config.terminator.as_deref().unwrap_or("lf")).map_err(config_error)?;
+        Ok(Box::new(SerialLineSource {
+            if_name: config.if_name.clone(),
+            settings,
+            terminator,
+            lines: LineBuffer::new(delimiter, max_line_len),
+            port: None,
+            buf: vec![0; 256],
+        }))
+    }
+}
+
+impl DataSource for SerialLineSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.settings.open(&self.if_name) {
+            Ok(port) => {
+                self.port = Some(port);
+                Ok(())
+            }
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let port = match self.port.as_mut() {
+            Some(port) => port,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        let mut msgs = Vec::new();
+        // 读到超时为止，每次最多读 4 KB，避免设备持续发送时一直不返回
+        for _ in 0..16 {
+            match port.read(&mut self.buf[..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    for line in self.lines.push(&self.buf[..n]) {
+                        msgs.push(OriginalData::new(&line));
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => break,
+                Err(err) => {
+                    debug!("read {} failed: {}", self.if_name, err);
+                    return Err(DataIfError::DataIfReadError);
+                }
+            }
+        }
+        Ok(msgs)
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let port = match self.port.as_mut() {
+            Some(port) => port,
+            None => return Err(DataIfError::DataIfWriteError),
+        };
+        let mut frame = msg.as_bytes().to_vec();
+        frame.extend_from_slice(&self.terminator);
+        match port.write_all(&frame) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                debug!("write {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfWriteError)
+            }
+        }
+    }
+
+    fn health(&self) -> Health {
+        match self.port {
+            Some(_) => Health::Up,
+            None => Health::Down(String::from("not opened")),
+        }
+    }
+}
+
+/// SPI 接口的 SX1276 LoRa 模块
+pub struct Sx1276Source {
+    if_name: String,
+    lora: LoraConfig,
+    // DIO0 和复位引脚所在的 GPIO 芯片及引脚编号
+    dio0_pin: Option<(String, u32)>,
+    reset_pin: Option<(String, u32)>,
+    spi: Option<Box<dyn RegisterAccess>>,
+    // 没有 DIO0 时轮询 REG_IRQ_FLAGS
+    dio0: Option<Dio0>,
+    // 下行报文的序号
+    tx_id: u8,
+    // 没有配置解码器时载荷按 UTF-8 文本处理
+    decoder: Option<PayloadDecoder>,
+    decode_errors: DecodeErrors,
+}
+
+// 没有指定 GPIO 芯片时使用的芯片
+pub const DEFAULT_GPIO_CHIP: &str = "/dev/gpiochip0";
+// 每次读取时等待 DIO0 的最长时间
+const DIO0_WAIT: Duration = Duration::from_millis(100);
+
+fn gpio_pin(name: &str, chip: &Option<String>, line: Option<u32>) -> Result<Option<(String, u32)>, String> {
+    match (chip, line) {
+        (Some(chip), Some(line)) => Ok(Some((chip.clone(), line))),
+        (None, Some(line)) => Ok(Some((DEFAULT_GPIO_CHIP.to_string(), line))),
+        (Some(_), None) => Err(format!("{}_line is required when {}_chip is set", name, name)),
+        (None, None) => Ok(None),
+    }
+}
+
+impl Sx1276Source {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        Ok(Box::new(Sx1276Source::new(config)?))
+    }
+
+    fn new(config: &DataIfConfig) -> Result<Self, DataIfError> {
+        let lora = config.lora.clone().unwrap_or_default();
+        let decoder = PayloadDecoder::from_config(config)?;
+        let pins = lora.check()
+            .and_then(|_| gpio_pin("dio0", &config.dio0_chip, config.dio0_line))
+            .and_then(|dio0| gpio_pin("reset", &config.reset_chip, config.reset_line).map(|reset| (dio0, reset)));
+        let (dio0_pin, reset_pin) = match pins {
+            Ok(pins) => pins,
+            Err(err) => {
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+        };
+        Ok(Sx1276Source {
+            if_name: config.if_name.clone(),
+            lora,
+            dio0_pin,
+            reset_pin,
+            spi: None,
+            dio0: None,
+            tx_id: 0,
+            decoder,
+            decode_errors: DecodeErrors::new(&config.if_name),
+        })
+    }
+
+    fn init_radio(&mut self, mut spi: Box<dyn RegisterAccess>) -> Result<(), DataIfError> {
+        if let Err(err) = SpiIf.init(&mut *spi, &self.lora) {
+            error!("init spi device failed: {}, {}", self.if_name, err);
+            return Err(DataIfError::DataIfInitError);
+        }
+        self.spi = Some(spi);
+        Ok(())
+    }
+}
+
+impl DataSource for Sx1276Source {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        if let Some((chip, line)) = &self.reset_pin {
+            if let Err(err) = reset_radio(chip, *line) {
+                error!("reset {} by {} line {} failed: {}", self.if_name, chip, line, err);
+                return Err(DataIfError::DataIfInitError);
+            }
+        }
+        let mut spi = match Spidev::open(&self.if_name) {
+            Ok(spi) => spi,
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                return Err(DataIfError::DataIfOpenError);
+            }
+        };
+        let options = SpidevOptions::new()
+            .bits_per_word(8)
+            .max_speed_hz(1000_000)
+            .mode(SpiModeFlags::SPI_MODE_0)
+            .build();
+        if let Err(err) = spi.configure(&options) {
+            error!("Open {} failed: {}", self.if_name, err);
+            return Err(DataIfError::DataIfOpenError);
+        }
+        self.init_radio(Box::new(spi))?;
+        if let Some((chip, line)) = &self.dio0_pin {
+            match Dio0::new(chip, *line) {
+                Ok(dio0) => self.dio0 = Some(dio0),
+                Err(err) => warn!("request dio0 {} line {} failed: {}, polling instead", chip, line, err),
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let spi = match self.spi.as_mut() {
+            Some(spi) => spi,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        // 没有收到数据时 SpiIf::read 也返回错误。先检查是否已经收到，再等待 DIO0，避免错过等待之前的上升沿
+        let mut result = SpiIf.read(&mut **spi, &self.lora);
+        if result.is_err() {
+            if let Some(dio0) = self.dio0.as_mut() {
+                match dio0.wait(DIO0_WAIT) {
+                    Ok(true) => result = SpiIf.read(&mut **spi, &self.lora),
+                    Ok(false) => {}
+                    Err(err) => {
+                        warn!("wait for dio0 of {} failed: {}, polling instead", self.if_name, err);
+                        self.dio0 = None;
+                    }
+                }
+            }
+        }
+        let packet = match result {
+            Ok(packet) => packet,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let sn_msg = match &self.decoder {
+            Some(decoder) => match self.decode_errors.decode(decoder, &packet.payload) {
+                Some(sn_msg) => sn_msg,
+                None => return Ok(Vec::new()),
+            },
+            None => match String::from_utf8(packet.payload) {
+                Ok(sn_msg) => sn_msg,
+                Err(_) => {
+                    debug!("get string error");
+                    return Ok(Vec::new());
+                }
+            },
+        };
+        if sn_msg.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut data = OriginalData::new(sn_msg.trim());
+        data.calls.insert(String::from("RSSI"), packet.rssi.into());
+        data.calls.insert(String::from("SNR"), packet.snr.into());
+        data.calls.insert(String::from("FREQ_ERR"), packet.freq_error.into());
+        data.calls.insert(String::from("LORA_TO"), packet.header.to.into());
+        data.calls.insert(String::from("LORA_FROM"), packet.header.from.into());
+        data.calls.insert(String::from("LORA_ID"), packet.header.id.into());
+        data.calls.insert(String::from("LORA_FLAGS"), packet.header.flags.into());
+        Ok(vec![data])
+    }
+
+    // 下行消息广播给所有节点
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let spi = match self.spi.as_mut() {
+            Some(spi) => spi,
+            None => return Err(DataIfError::DataIfWriteError),
+        };
+        let header = RhHeader {
+            to: RH_BROADCAST_ADDRESS,
+            from: self.lora.address,
+            id: self.tx_id,
+            flags: 0,
+        };
+        self.tx_id = self.tx_id.wrapping_add(1);
+        // 等待时间为发送时间的两倍，至少 100 ms
+        let timeout = self.lora.time_on_air(RH_HEADER_LEN + msg.len()) * 2 + Duration::from_millis(100);
+        match SpiIf.send(&mut **spi, &header, msg.as_bytes(), timeout, self.dio0.as_mut()) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("send to {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfWriteError)
+            }
+        }
+    }
+
+    fn health(&self) -> Health {
+        match (&self.spi, &self.dio0_pin, &self.dio0) {
+            (None, _, _) => Health::Down(String::from("not opened")),
+            (Some(_), Some(_), None) => Health::Degraded(String::from("dio0 unavailable, polling")),
+            _ => Health::Up,
+        }
+    }
+
+    // 有 DIO0 时在 read 中等待
+    fn poll_interval(&self) -> Duration {
+        match self.dio0 {
+            Some(_) => Duration::from_millis(0),
+            None => Duration::from_millis(100),
+        }
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        self.decode_errors.take_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_source::{DataIfError, DataSource, Health, Registry, Sx1276Source};
+    use crate::sx127x_sim::Sx127xSim;
+    use crate::types::{DataIfConfig, OriginalData};
+
+    struct Counter {
+        n: u32,
+    }
+
+    impl DataSource for Counter {
+        fn open(&mut self) -> Result<(), DataIfError> {
+            Ok(())
+        }
+        fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+            self.n += 1;
+            Ok(vec![OriginalData::new(&format!("{{\"n\": {}}}", self.n))])
+        }
+        fn write(&mut self, _msg: &str) -> Result<(), DataIfError> {
+            Ok(())
+        }
+        fn health(&self) -> Health {
+            Health::Up
+        }
+    }
+
+    fn build_counter(_config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        Ok(Box::new(Counter { n: 0 }))
+    }
+
+    #[test]
+    fn registry_build() {
+        let mut registry = Registry::default();
+        let config: DataIfConfig = toml::from_str("if_name = \"a.txt\"\nif_type = \"text_file\"").unwrap();
+        assert!(registry.check(&config).is_ok());
+        registry.register("counter", build_counter, &["poll_interval_ms"]);
+        let config: DataIfConfig = toml::from_str("if_name = \"c0\"\nif_type = \"counter\"").unwrap();
+        let mut source = registry.build(&config).unwrap();
+        source.open().unwrap();
+        assert_eq!(source.read().unwrap()[0].msg, "{\"n\": 1}");
+        let config: DataIfConfig = toml::from_str("if_name = \"x\"\nif_type = \"unknown\"").unwrap();
+        match registry.build(&config) {
+            Err(DataIfError::DataIfUnknownType) => {}
+            _ => panic!("unknown if_type should fail"),
+        }
+    }
+
+    #[test]
+    fn registry_unsupported_keys() {
+        let mut registry = Registry::default();
+        registry.register("counter", build_counter, &["poll_interval_ms"]);
+        let config: DataIfConfig = toml::from_str("if_name = \"c0\"\nif_type = \"counter\"\npoll_interval_ms = 10\ntemplate = \"t\"").unwrap();
+        assert!(registry.check(&config).is_ok());
+        let config: DataIfConfig = toml::from_str("if_name = \"0.0.0.0:5000\"\nif_type = \"udp\"\nbaud_rate = 9600\nparity = \"even\"").unwrap();
+        match registry.build(&config) {
+            Err(DataIfError::DataIfConfigError(err)) => assert_eq!(err, "baud_rate, parity not supported by udp"),
+            _ => panic!("baud_rate on udp should fail"),
+        }
+        let config: DataIfConfig = toml::from_str("if_name = \"/dev/ttyUSB0\"\nif_type = \"serial_line\"\nbaud_rate = 9600\ndecoder = \"json\"").unwrap();
+        match registry.check(&config) {
+            Err(DataIfError::DataIfConfigError(err)) => assert_eq!(err, "decoder not supported by serial_line"),
+            _ => panic!("decoder on serial_line should fail"),
+        }
+    }
+
+    #[test]
+    fn sx1276_config() {
+        let registry = Registry::default();
+        let config: DataIfConfig = toml::from_str("if_name = \"/dev/spidev0.0\"\nif_type = \"spi_sx1276\"\ndio0_line = 25\nreset_line = 17").unwrap();
+        assert!(registry.build(&config).is_ok());
+        let config: DataIfConfig = toml::from_str("if_name = \"/dev/spidev0.0\"\nif_type = \"spi_sx1276\"\ndio0_chip = \"/dev/gpiochip0\"").unwrap();
+        match registry.build(&config) {
+            Err(DataIfError::DataIfConfigError(_)) => {}
+            _ => panic!("dio0_chip without dio0_line should fail"),
+        }
+        let config: DataIfConfig = toml::from_str("if_name = \"/dev/spidev0.0\"\nif_type = \"spi_sx1276\"\n[lora]\nspreading_factor = 13").unwrap();
+        assert!(registry.build(&config).is_err());
+    }
+
+    #[test]
+    fn sx1276_with_simulator() {
+        let config: DataIfConfig = toml::from_str("if_name = \"sim\"\nif_type = \"spi_sx1276\"\n[lora]\nfrequency = 868100000\naddress = 0x01").unwrap();
+        let sim = Sx127xSim::new();
+        let mut source = Sx1276Source::new(&config).unwrap();
+        source.init_radio(Box::new(sim.clone())).unwrap();
+        assert_eq!(source.health(), Health::Up);
+        assert!(source.read().unwrap().is_empty());
+
+        let mut packet = vec![0xFF, 0x03, 0x07, 0x00];
+        packet.extend_from_slice(b"{\"l\":\"SN-001\",\"t\": 27.45}\n");
+        assert!(sim.receive(&packet, 60, 38, -200));
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].msg, "{\"l\":\"SN-001\",\"t\": 27.45}");
+        assert_eq!(msgs[0].calls["RSSI"], -93);
+        assert_eq!(msgs[0].calls["LORA_FROM"], 3);
+
+        source.write("{\"led\": 1}").unwrap();
+        source.write("{\"led\": 0}").unwrap();
+        let sent = sim.sent();
+        assert_eq!(sent[0][..4], [0xFF, 0x01, 0x00, 0x00]);
+        assert_eq!(sent[1][..4], [0xFF, 0x01, 0x01, 0x00]);
+        assert_eq!(&sent[1][4..], b"{\"led\": 0}");
+    }
+    #[test]
+    fn sx1276_decoder() {
+        let config: DataIfConfig = toml::from_str("if_name = \"sim\"\nif_type = \"spi_sx1276\"\ndecoder = \"cayenne_lpp\"").unwrap();
+        let sim = Sx127xSim::new();
+        let mut source = Sx1276Source::new(&config).unwrap();
+        source.init_radio(Box::new(sim.clone())).unwrap();
+        assert!(sim.receive(&[0xFF, 0x03, 0x07, 0x00, 0x01, 0x67, 0x01, 0x10], 60, 38, 0));
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs[0].msg, "{\"temperature_1\":27.2}");
+        assert_eq!(msgs[0].calls["LORA_FROM"], 3);
+
+        // 解码失败的报文被丢弃并计数
+        assert!(sim.receive(&[0xFF, 0x03, 0x08, 0x00, 0x01, 0x67, 0x01], 60, 38, 0));
+        assert!(source.read().unwrap().is_empty());
+        let events = source.log_events();
+        assert_eq!(events[0].event, "decode_error");
+        assert_eq!(events[0].fields["decode_errors"], 1);
+
+        let config: DataIfConfig = toml::from_str("if_name = \"sim\"\nif_type = \"spi_sx1276\"\ndecoder = \"protobuf\"").unwrap();
+        assert!(Sx1276Source::new(&config).is_err());
+    }
+}
@@ -0,0 +1,616 @@
+//! 二进制载荷解码器，把数据接口收到的载荷解码成 JSON，之后再按数据模板转换。
+//!
+//! 数据接口通过 `decoder` 配置解码器，`serial_port`、`spi_sx1276`、`udp` 支持。解码失败时丢弃该载荷，
+//! 由 `DecodeErrors` 计数并写入日志。
+
+extern crate log;
+
+use std::time::{Duration, Instant};
+use log::{error, warn};
+use crate::data_source::{DataIfError, LogEvent};
+use crate::struct_decoder::{StructFieldConfig, StructLayout};
+use crate::types::DataIfConfig;
+
+// CBOR、MessagePack 的最大嵌套层数
+const MAX_DEPTH: usize = 32;
+// decode_error 日志事件的最小间隔
+const DECODE_ERROR_EVENT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 载荷解码器
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadDecoder {
+    // 载荷是 JSON 文本
+    Json,
+    // 载荷是 UTF-8 文本，解码为 {"text": "..."}
+    Text,
+    // 任意二进制，解码为 {"data": "十六进制字符串", "len": 长度}
+    Hex,
+    Cbor,
+    MessagePack,
+    // Cayenne LPP，解码为 {"temperature_1": 27.2, ...}
+    CayenneLpp,
+    // 按 struct_field 定义的布局解码 C 结构体
+    Struct(StructLayout),
+}
+
+impl PayloadDecoder {
+    /// `fields` 为 struct_field 的定义，仅 struct 解码器使用
+    pub fn new(name: &str, fields: Option<&[StructFieldConfig]>) -> Result<Self, String> {
+        match (name, fields) {
+            ("struct", Some(fields)) => return StructLayout::new(fields).map(PayloadDecoder::Struct),
+            ("struct", None) => return Err(String::from("decoder struct requires struct_field")),
+            (_, Some(_)) => return Err(format!("struct_field is only used by decoder struct, not {}", name)),
+            _ => {}
+        }
+        match name {
+            "json" => Ok(PayloadDecoder::Json),
+            "text" => Ok(PayloadDecoder::Text),
+            "hex" => Ok(PayloadDecoder::Hex),
+            "cbor" => Ok(PayloadDecoder::Cbor),
+            "msgpack" => Ok(PayloadDecoder::MessagePack),
+            "cayenne_lpp" => Ok(PayloadDecoder::CayenneLpp),
+            _ => Err(format!("unknown decoder: {}, expected \"json\", \"text\", \"hex\", \"cbor\", \"msgpack\", \"cayenne_lpp\" or \"struct\"", name)),
+        }
+    }
+
+    /// 数据接口配置的解码器，没有配置时返回 None
+    pub fn from_config(config: &DataIfConfig) -> Result<Option<Self>, DataIfError> {
+        match &config.decoder {
+            Some(name) => PayloadDecoder::new(name, config.struct_field.as_deref()).map(Some).map_err(|err| {
+                error!("{}: {}", config.if_name, err);
+                DataIfError::DataIfConfigError(err)
+            }),
+            None if config.struct_field.is_some() => {
+                error!("{}: struct_field is only used by decoder struct", config.if_name);
+                Err(DataIfError::DataIfConfigError(String::from("struct_field is only used by decoder struct")))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PayloadDecoder::Json => "json",
+            PayloadDecoder::Text => "text",
+            PayloadDecoder::Hex => "hex",
+            PayloadDecoder::Cbor => "cbor",
+            PayloadDecoder::MessagePack => "msgpack",
+            PayloadDecoder::CayenneLpp => "cayenne_lpp",
+            PayloadDecoder::Struct(_) => "struct",
+        }
+    }
+
+    pub fn decode(&self, payload: &[u8]) -> Result<json::JsonValue, String> {
+        match self {
+            PayloadDecoder::Json => {
+                let text = std::str::from_utf8(payload).map_err(|err| err.to_string())?;
+                json::parse(text).map_err(|err| err.to_string())
+            }
+            PayloadDecoder::Text => {
+                let text = std::str::from_utf8(payload).map_err(|err| err.to_string())?;
+                let mut obj = json::JsonValue::new_object();
+                obj["text"] = text.into();
+                Ok(obj)
+            }
+            PayloadDecoder::Hex => {
+                let mut obj = json::JsonValue::new_object();
+                obj["data"] = to_hex(payload).into();
+                obj["len"] = payload.len().into();
+                Ok(obj)
+            }
+            PayloadDecoder::Cbor => Reader::new(payload).finish(|reader| reader.cbor(0)),
+            PayloadDecoder::MessagePack => Reader::new(payload).finish(|reader| reader.msgpack(0)),
+            PayloadDecoder::CayenneLpp => decode_cayenne_lpp(payload),
+            PayloadDecoder::Struct(layout) => layout.decode(payload),
+        }
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// NaN、无穷大在 JSON 中没有对应的值
+pub fn float(value: f64) -> json::JsonValue {
+    if value.is_finite() {
+        value.into()
+    } else {
+        json::JsonValue::Null
+    }
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let value = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 { -value } else { value }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    /// 解码一个值，之后不能有多余的字节
+    fn finish<F>(mut self, decode: F) -> Result<json::JsonValue, String>
+    where
+        F: FnOnce(&mut Reader<'a>) -> Result<json::JsonValue, String>,
+    {
+        let value = decode(&mut self)?;
+        if self.pos != self.data.len() {
+            return Err(format!("{} trailing bytes", self.data.len() - self.pos));
+        }
+        Ok(value)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err(String::from("unexpected end of payload"));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, String> {
+        Ok(self.bytes(len)?.iter().fold(0_u64, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, String> {
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|err| err.to_string())
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.data.get(self.pos).copied().ok_or_else(|| String::from("unexpected end of payload"))
+    }
+
+    // 对象的键，不是字符串的键转换为字符串
+    fn key(value: json::JsonValue) -> String {
+        match value.as_str() {
+            Some(key) => key.to_string(),
+            None => value.dump(),
+        }
+    }
+
+    // CBOR 头部的参数，None 表示不定长
+    fn cbor_argument(&mut self, info: u8) -> Result<Option<u64>, String> {
+        match info {
+            0..=23 => Ok(Some(info as u64)),
+            24 => self.uint(1).map(Some),
+            25 => self.uint(2).map(Some),
+            26 => self.uint(4).map(Some),
+            27 => self.uint(8).map(Some),
+            31 => Ok(None),
+            _ => Err(format!("invalid CBOR additional information: {}", info)),
+        }
+    }
+
+    fn cbor_length(&mut self, info: u8) -> Result<Option<usize>, String> {
+        match self.cbor_argument(info)? {
+            Some(len) if len > (self.data.len() - self.pos) as u64 => Err(String::from("unexpected end of payload")),
+            len => Ok(len.map(|len| len as usize)),
+        }
+    }
+
+    fn cbor_break(&mut self) -> Result<bool, String> {
+        if self.peek()? == 0xff {
+            self.pos += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    // 不定长的字节串、文本串由多个定长的片段组成
+    fn cbor_chunks(&mut self, major: u8) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        while !self.cbor_break()? {
+            let initial = self.bytes(1)?[0];
+            if initial >> 5 != major {
+                return Err(String::from("invalid CBOR indefinite-length string"));
+            }
+            match self.cbor_length(initial & 0x1f)? {
+                Some(len) => bytes.extend_from_slice(self.bytes(len)?),
+                None => return Err(String::from("invalid CBOR indefinite-length string")),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn cbor(&mut self, depth: usize) -> Result<json::JsonValue, String> {
+        if depth > MAX_DEPTH {
+            return Err(String::from("CBOR nested too deep"));
+        }
+        let initial = self.bytes(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        match major {
+            0 => match self.cbor_argument(info)? {
+                Some(value) => Ok(value.into()),
+                None => Err(String::from("invalid CBOR integer")),
+            },
+            1 => match self.cbor_argument(info)? {
+                Some(value) if value <= i64::MAX as u64 => Ok((-1 - value as i64).into()),
+                Some(value) => Ok(float(-1.0 - value as f64)),
+                None => Err(String::from("invalid CBOR integer")),
+            },
+            2 => {
+                let bytes = match self.cbor_length(info)? {
+                    Some(len) => self.bytes(len)?.to_vec(),
+                    None => self.cbor_chunks(2)?,
+                };
+                Ok(to_hex(&bytes).into())
+            }
+            3 => {
+                let bytes = match self.cbor_length(info)? {
+                    Some(len) => self.bytes(len)?.to_vec(),
+                    None => self.cbor_chunks(3)?,
+                };
+                String::from_utf8(bytes).map(|text| text.into()).map_err(|err| err.to_string())
+            }
+            4 => {
+                let mut array = json::JsonValue::new_array();
+                match self.cbor_length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            let _ = array.push(self.cbor(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while !self.cbor_break()? {
+                            let _ = array.push(self.cbor(depth + 1)?);
+                        }
+                    }
+                }
+                Ok(array)
+            }
+            5 => {
+                let mut obj = json::JsonValue::new_object();
+                match self.cbor_length(info)? {
+                    Some(len) => {
+                        for _ in 0..len {
+                            let key = Reader::key(self.cbor(depth + 1)?);
+                            obj[key.as_str()] = self.cbor(depth + 1)?;
+                        }
+                    }
+                    None => {
+                        while !self.cbor_break()? {
+                            let key = Reader::key(self.cbor(depth + 1)?);
+                            obj[key.as_str()] = self.cbor(depth + 1)?;
+                        }
+                    }
+                }
+                Ok(obj)
+            }
+            // 标签（例如时间戳）只保留内容
+            6 => {
+                self.cbor_argument(info)?;
+                self.cbor(depth + 1)
+            }
+            _ => match info {
+                20 => Ok(false.into()),
+                21 => Ok(true.into()),
+                22 | 23 => Ok(json::JsonValue::Null),
+                25 => Ok(float(half_to_f64(self.uint(2)? as u16))),
+                26 => Ok(float(f32::from_bits(self.uint(4)? as u32) as f64)),
+                27 => Ok(float(f64::from_bits(self.uint(8)?))),
+                _ => Err(format!("unsupported CBOR simple value: {}", info)),
+            },
+        }
+    }
+
+    fn msgpack_array(&mut self, len: usize, depth: usize) -> Result<json::JsonValue, String> {
+        let mut array = json::JsonValue::new_array();
+        for _ in 0..len {
+            let _ = array.push(self.msgpack(depth + 1)?);
+        }
+        Ok(array)
+    }
+
+    fn msgpack_map(&mut self, len: usize, depth: usize) -> Result<json::JsonValue, String> {
+        let mut obj = json::JsonValue::new_object();
+        for _ in 0..len {
+            let key = Reader::key(self.msgpack(depth + 1)?);
+            obj[key.as_str()] = self.msgpack(depth + 1)?;
+        }
+        Ok(obj)
+    }
+
+    // 扩展类型解码为 {"type": 类型, "data": "十六进制字符串"}
+    fn msgpack_ext(&mut self, len: usize) -> Result<json::JsonValue, String> {
+        let ext_type = self.bytes(1)?[0] as i8;
+        let mut obj = json::JsonValue::new_object();
+        obj["type"] = ext_type.into();
+        obj["data"] = to_hex(self.bytes(len)?).into();
+        Ok(obj)
+    }
+
+    fn msgpack(&mut self, depth: usize) -> Result<json::JsonValue, String> {
+        if depth > MAX_DEPTH {
+            return Err(String::from("MessagePack nested too deep"));
+        }
+        let marker = self.bytes(1)?[0];
+        match marker {
+            0x00..=0x7f => Ok(marker.into()),
+            0x80..=0x8f => self.msgpack_map((marker & 0x0f) as usize, depth),
+            0x90..=0x9f => self.msgpack_array((marker & 0x0f) as usize, depth),
+            0xa0..=0xbf => self.string((marker & 0x1f) as usize).map(|text| text.into()),
+            0xc0 => Ok(json::JsonValue::Null),
+            0xc2 => Ok(false.into()),
+            0xc3 => Ok(true.into()),
+            0xc4..=0xc6 => {
+                let len = self.uint(1 << (marker - 0xc4))? as usize;
+                Ok(to_hex(self.bytes(len)?).into())
+            }
+            0xc7..=0xc9 => {
+                let len = self.uint(1 << (marker - 0xc7))? as usize;
+                self.msgpack_ext(len)
+            }
+            0xca => Ok(float(f32::from_bits(self.uint(4)? as u32) as f64)),
+            0xcb => Ok(float(f64::from_bits(self.uint(8)?))),
+            0xcc..=0xcf => Ok(self.uint(1 << (marker - 0xcc))?.into()),
+            0xd0 => Ok((self.uint(1)? as u8 as i8).into()),
+            0xd1 => Ok((self.uint(2)? as u16 as i16).into()),
+            0xd2 => Ok((self.uint(4)? as u32 as i32).into()),
+            0xd3 => Ok((self.uint(8)? as i64).into()),
+            0xd4..=0xd8 => self.msgpack_ext(1 << (marker - 0xd4)),
+            0xd9..=0xdb => {
+                let len = self.uint(1 << (marker - 0xd9))? as usize;
+                self.string(len).map(|text| text.into())
+            }
+            0xdc | 0xdd => {
+                let len = self.uint(if marker == 0xdc { 2 } else { 4 })? as usize;
+                self.msgpack_array(len, depth)
+            }
+            0xde | 0xdf => {
+                let len = self.uint(if marker == 0xde { 2 } else { 4 })? as usize;
+                self.msgpack_map(len, depth)
+            }
+            0xe0..=0xff => Ok((marker as i8).into()),
+            _ => Err(format!("invalid MessagePack marker: 0x{:02x}", marker)),
+        }
+    }
+}
+
+// Cayenne LPP 的数据类型：类型、名称、每个值的字节数、是否有符号、每个值的名称和分辨率的倒数，
+// 只有一个值的类型名称为空
+struct LppType {
+    id: u8,
+    name: &'static str,
+    size: usize,
+    signed: bool,
+    values: &'static [(&'static str, f64)],
+}
+
+const LPP_TYPES: [LppType; 12] = [
+    LppType { id: 0, name: "digital_input", size: 1, signed: false, values: &[("", 1.0)] },
+    LppType { id: 1, name: "digital_output", size: 1, signed: false, values: &[("", 1.0)] },
+    LppType { id: 2, name: "analog_input", size: 2, signed: true, values: &[("", 100.0)] },
+    LppType { id: 3, name: "analog_output", size: 2, signed: true, values: &[("", 100.0)] },
+    LppType { id: 101, name: "illuminance", size: 2, signed: false, values: &[("", 1.0)] },
+    LppType { id: 102, name: "presence", size: 1, signed: false, values: &[("", 1.0)] },
+    LppType { id: 103, name: "temperature", size: 2, signed: true, values: &[("", 10.0)] },
+    LppType { id: 104, name: "humidity", size: 1, signed: false, values: &[("", 2.0)] },
+    LppType { id: 113, name: "accelerometer", size: 2, signed: true, values: &[("x", 1000.0), ("y", 1000.0), ("z", 1000.0)] },
+    LppType { id: 115, name: "barometer", size: 2, signed: false, values: &[("", 10.0)] },
+    LppType { id: 134, name: "gyrometer", size: 2, signed: true, values: &[("x", 100.0), ("y", 100.0), ("z", 100.0)] },
+    LppType { id: 136, name: "gps", size: 3, signed: true, values: &[("latitude", 10000.0), ("longitude", 10000.0), ("altitude", 100.0)] },
+];
+
+fn lpp_value(bytes: &[u8], signed: bool) -> f64 {
+    let raw = bytes.iter().fold(0_u32, |value, byte| (value << 8) | *byte as u32);
+    let bits = bytes.len() * 8;
+    if signed && raw >> (bits - 1) != 0 {
+        raw as f64 - (1_u64 << bits) as f64
+    } else {
+        raw as f64
+    }
+}
+
+/// 解码 Cayenne LPP，每个值由通道、类型、数据组成，输出的属性名为 "类型_通道"
+pub fn decode_cayenne_lpp(payload: &[u8]) -> Result<json::JsonValue, String> {
+    let mut obj = json::JsonValue::new_object();
+    let mut reader = Reader::new(payload);
+    while reader.pos < payload.len() {
+        let header = reader.bytes(2)?;
+        let (channel, type_id) = (header[0], header[1]);
+        let lpp_type = match LPP_TYPES.iter().find(|lpp_type| lpp_type.id == type_id) {
+            Some(lpp_type) => lpp_type,
+            None => return Err(format!("unknown Cayenne LPP type: {}", type_id)),
+        };
+        let data = reader.bytes(lpp_type.size * lpp_type.values.len())?;
+        let name = format!("{}_{}", lpp_type.name, channel);
+        let mut values = data.chunks(lpp_type.size)
+            .zip(lpp_type.values.iter())
+            .map(|(bytes, (field, divisor))| (*field, lpp_value(bytes, lpp_type.signed) / divisor));
+        match lpp_type.values {
+            [("", _)] => {
+                if let Some((_, value)) = values.next() {
+                    obj[name.as_str()] = value.into();
+                }
+            }
+            _ => {
+                let mut value = json::JsonValue::new_object();
+                for (field, v) in values {
+                    value[field] = v.into();
+                }
+                obj[name.as_str()] = value;
+            }
+        }
+    }
+    Ok(obj)
+}
+
+/// 解析十六进制字符串，字节之间可以有空白、`:`、`,`、`-`，可以带 `0x` 前缀
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for token in text.split(|c: char| c.is_whitespace() || c == ':' || c == ',' || c == '-') {
+        let hex = token.strip_prefix("0x").or_else(|| token.strip_prefix("0X")).unwrap_or(token);
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(format!("invalid hex: {}", token));
+        }
+        for i in (0..hex.len()).step_by(2) {
+            let byte = u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("invalid hex: {}", token))?;
+            bytes.push(byte);
+        }
+    }
+    Ok(bytes)
+}
+
+/// 统计数据接口解码失败的次数，每次失败写入日志，并最多每分钟产生一次 decode_error 日志事件
+pub struct DecodeErrors {
+    if_name: String,
+    count: u64,
+    last_event: Option<Instant>,
+    events: Vec<LogEvent>,
+}
+
+impl DecodeErrors {
+    pub fn new(if_name: &str) -> Self {
+        DecodeErrors {
+            if_name: if_name.to_string(),
+            count: 0,
+            last_event: None,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, err: &str) {
+        self.count += 1;
+        warn!("{}: decode payload failed ({} in total): {}", self.if_name, self.count, err);
+        let report = match self.last_event {
+            Some(last) => last.elapsed() >= DECODE_ERROR_EVENT_INTERVAL,
+            None => true,
+        };
+        if report {
+            let mut fields = json::JsonValue::new_object();
+            fields["error"] = err.into();
+            fields["decode_errors"] = self.count.into();
+            self.events.push(LogEvent {
+                event: String::from("decode_error"),
+                message: format!("decode payload failed ({} in total): {}", self.count, err),
+                fields,
+            });
+            self.last_event = Some(Instant::now());
+        }
+    }
+
+    /// 用 `decoder` 解码载荷，返回 JSON 字符串，失败时计数并返回 None
+    pub fn decode(&mut self, decoder: &PayloadDecoder, payload: &[u8]) -> Option<String> {
+        match decoder.decode(payload) {
+            Ok(value) => Some(value.dump()),
+            Err(err) => {
+                self.record(&format!("{}: {}", decoder.name(), err));
+                None
+            }
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<LogEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decoder::*;
+
+    #[test]
+    fn payload_decoder() {
+        let decoded = PayloadDecoder::Json.decode(b"{\"t\": 27.45}").unwrap();
+        assert_eq!(decoded["t"], 27.45);
+        assert!(PayloadDecoder::Json.decode(b"27.45,").is_err());
+        assert_eq!(PayloadDecoder::Text.decode(b"low battery").unwrap()["text"], "low battery");
+        assert!(PayloadDecoder::Text.decode(&[0xff, 0xfe]).is_err());
+        let decoded = PayloadDecoder::Hex.decode(&[0x01, 0xab]).unwrap();
+        assert_eq!(decoded["data"], "01ab");
+        assert_eq!(decoded["len"], 2);
+        assert!(PayloadDecoder::new("protobuf", None).is_err());
+        assert!(PayloadDecoder::new("struct", None).is_err());
+        assert!(PayloadDecoder::new("json", Some(&[])).is_err());
+
+        assert_eq!(parse_hex("01ab 0x02:ff,10-0A\n").unwrap(), vec![0x01, 0xab, 0x02, 0xff, 0x10, 0x0a]);
+        assert!(parse_hex("01a").is_err());
+        assert!(parse_hex("0g").is_err());
+    }
+
+    #[test]
+    fn cbor_decoder() {
+        let cbor = PayloadDecoder::Cbor;
+        // RFC 8949 附录 A 的示例
+        assert_eq!(cbor.decode(&[0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03]).unwrap().dump(), r#"{"a":1,"b":[2,3]}"#);
+        assert_eq!(cbor.decode(&[0xbf, 0x61, 0x61, 0x01, 0x61, 0x62, 0x9f, 0x02, 0x03, 0xff, 0xff]).unwrap().dump(), r#"{"a":1,"b":[2,3]}"#);
+        assert_eq!(cbor.decode(&[0x39, 0x03, 0xe7]).unwrap(), -1000);
+        assert_eq!(cbor.decode(&[0xf9, 0x3e, 0x00]).unwrap(), 1.5);
+        assert_eq!(cbor.decode(&[0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]).unwrap(), 1.1);
+        assert_eq!(cbor.decode(&[0xf5]).unwrap(), true);
+        assert!(cbor.decode(&[0xf6]).unwrap().is_null());
+        assert!(cbor.decode(&[0xf9, 0x7c, 0x00]).unwrap().is_null());
+        assert_eq!(cbor.decode(&[0x7f, 0x62, 0x73, 0x74, 0x62, 0x61, 0x72, 0xff]).unwrap(), "star");
+        assert_eq!(cbor.decode(&[0x42, 0x01, 0xab]).unwrap(), "01ab");
+        // 时间戳标签
+        assert_eq!(cbor.decode(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]).unwrap(), 1363896240);
+        assert!(cbor.decode(&[0x82, 0x01]).is_err());
+        assert!(cbor.decode(&[0x01, 0x02]).is_err());
+        assert!(cbor.decode(&[0x7b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(cbor.decode(&[0x81; 64]).is_err());
+    }
+
+    #[test]
+    fn msgpack_decoder() {
+        let msgpack = PayloadDecoder::MessagePack;
+        let payload = [0x82, 0xa1, 0x74, 0xcb, 0x40, 0x3b, 0x80, 0, 0, 0, 0, 0, 0xa2, 0x6f, 0x6b, 0xc3];
+        assert_eq!(msgpack.decode(&payload).unwrap().dump(), r#"{"t":27.5,"ok":true}"#);
+        assert_eq!(msgpack.decode(&[0x93, 0x01, 0xfb, 0xcd, 0x01, 0x00]).unwrap().dump(), "[1,-5,256]");
+        assert_eq!(msgpack.decode(&[0xd1, 0xfc, 0x18]).unwrap(), -1000);
+        assert_eq!(msgpack.decode(&[0xca, 0x3f, 0xc0, 0x00, 0x00]).unwrap(), 1.5);
+        assert_eq!(msgpack.decode(&[0xc4, 0x02, 0x01, 0xab]).unwrap(), "01ab");
+        assert_eq!(msgpack.decode(&[0xd4, 0x05, 0x10]).unwrap().dump(), r#"{"type":5,"data":"10"}"#);
+        assert!(msgpack.decode(&[0xc0]).unwrap().is_null());
+        assert!(msgpack.decode(&[0xc1]).is_err());
+        assert!(msgpack.decode(&[0x92, 0x01]).is_err());
+        assert!(msgpack.decode(&[0xa3, 0x61]).is_err());
+    }
+
+    #[test]
+    fn cayenne_lpp_decoder() {
+        let lpp = PayloadDecoder::CayenneLpp;
+        // Cayenne LPP 文档中的示例
+        let decoded = lpp.decode(&[0x03, 0x67, 0x01, 0x10, 0x05, 0x67, 0x00, 0xff]).unwrap();
+        assert_eq!(decoded.dump(), r#"{"temperature_3":27.2,"temperature_5":25.5}"#);
+        assert_eq!(lpp.decode(&[0x01, 0x67, 0xff, 0xd7]).unwrap()["temperature_1"], -4.1);
+        let decoded = lpp.decode(&[0x06, 0x71, 0x04, 0xd2, 0xfb, 0x2e, 0x00, 0x00]).unwrap();
+        assert_eq!(decoded.dump(), r#"{"accelerometer_6":{"x":1.234,"y":-1.234,"z":0}}"#);
+        let decoded = lpp.decode(&[0x01, 0x88, 0x06, 0x76, 0x5f, 0xf2, 0x96, 0x0a, 0x00, 0x03, 0xe8]).unwrap();
+        assert_eq!(decoded["gps_1"]["latitude"], 42.3519);
+        assert_eq!(decoded["gps_1"]["longitude"], -87.9094);
+        assert_eq!(decoded["gps_1"]["altitude"], 10);
+        let decoded = lpp.decode(&[0x02, 0x68, 0x61, 0x07, 0x73, 0x27, 0x7f]).unwrap();
+        assert_eq!(decoded.dump(), r#"{"humidity_2":48.5,"barometer_7":1011.1}"#);
+        assert!(lpp.decode(&[0x01, 0x67, 0x01]).is_err());
+        assert!(lpp.decode(&[0x01, 0x99, 0x00]).is_err());
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut errors = DecodeErrors::new("uart");
+        assert_eq!(errors.decode(&PayloadDecoder::Cbor, &[0xf5]), Some(String::from("true")));
+        assert_eq!(errors.decode(&PayloadDecoder::Cbor, &[0x82]), None);
+        assert_eq!(errors.decode(&PayloadDecoder::Cbor, &[0x82]), None);
+        assert_eq!(errors.count, 2);
+        // 一分钟内只产生一次日志事件
+        let events = errors.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "decode_error");
+        assert_eq!(events[0].fields["decode_errors"], 1);
+    }
+}
@@ -2,13 +2,19 @@ extern crate min_rs as min;
 extern crate log;
 
 use std::io::prelude::*;
-use std::io;
+use std::io::{self, SeekFrom};
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
 use std::cell::RefCell;
 use std::sync::{Arc, Mutex};
-use log::{debug, trace};
-use serialport::SerialPort;
+use log::{debug, trace, warn};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use serde_derive::Deserialize;
 use spidev::{Spidev, SpidevTransfer};
+use gpio_cdev::{Chip, EventRequestFlags, LineEventHandle, LineRequestFlags};
+use std::os::unix::io::AsRawFd;
 use std::thread;
+use std::time::Duration;
 
 pub const REG_OPMODE: u8 = 0x01;
 pub const REG_FIFO: u8 = 0x00;
@@ -38,6 +44,9 @@ pub const REG_SYNC_WORD: u8 = 0x39;
 pub const REG_DIO_MAPPING1: u8 = 0x40;
 pub const REG_VERSION: u8 = 0x42;
 pub const REG_PA_DAC: u8 = 0x4d;
+pub const REG_FEI_MSB: u8 = 0x28;
+pub const REG_FEI_MID: u8 = 0x29;
+pub const REG_FEI_LSB: u8 = 0x2A;
 
 // LOW NOISE AMPLIFIER
 pub const REG_LNA: u8 = 0x0C;
@@ -70,28 +79,459 @@ pub const FHSS_CHANGE_CHANNEL: u8 = 0x02;
 pub const CAD_DETECTED: u8 = 0x01;
 
 pub const FREQ: u32 = 434000000; // 434 Mhz
+// REG_MODEM_CONFIG3
+pub const LOW_DATA_RATE_OPTIMIZE: u8 = 0x08;
 
-enum ModemConfigChoice {
-    Bw125Cr45Sf128, //< Bw = 125 kHz, Cr = 4/5, Sf = 128chips/symbol, CRC on. Default medium range
-    Bw500Cr45Sf128, //< Bw = 500 kHz, Cr = 4/5, Sf = 128chips/symbol, CRC on. Fast+short range
-    Bw31_25Cr48Sf512, //< Bw = 31.25 kHz, Cr = 4/8, Sf = 512chips/symbol, CRC on. Slow+long range
-    Bw125Cr48Sf4096, //< Bw = 125 kHz, Cr = 4/8, Sf = 4096chips/symbol, CRC on. Slow+long range
+// RadioHead 报头
+pub const RH_HEADER_LEN: usize = 4;
+pub const RH_BROADCAST_ADDRESS: u8 = 0xFF;
+pub const RH_MAX_PAYLOAD_LEN: usize = 255 - RH_HEADER_LEN;
+
+// SX1276 支持的带宽（Hz），下标为 REG_MODEM_CONFIG 中 Bw 的值
+pub const BANDWIDTHS: [u32; 10] = [7800, 10400, 15600, 20800, 31250, 41700, 62500, 125000, 250000, 500000];
+
+/// RadioHead 风格的 4 字节报头，位于每个 LoRa 报文的开头
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RhHeader {
+    pub to: u8,
+    pub from: u8,
+    pub id: u8,
+    pub flags: u8,
+}
+
+impl RhHeader {
+    pub fn to_bytes(&self) -> [u8; RH_HEADER_LEN] {
+        [self.to, self.from, self.id, self.flags]
+    }
+}
+
+/// 收到的 LoRa 报文
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoraPacket {
+    pub header: RhHeader,
+    // 报文的信号强度，单位：dBm
+    pub rssi: i16,
+    // 信噪比，单位：dB
+    pub snr: f32,
+    // 频率误差，单位：Hz
+    pub freq_error: i32,
+    pub payload: Vec<u8>,
 }
 
-#[derive(Debug,Copy,Clone)]
-pub struct FileIf;
+/// 根据 REG_PKT_RSSI_VALUE 计算报文的信号强度，见 SX1276 数据手册 5.5.5
+pub fn packet_rssi(pkt_rssi: u8, snr: f32, frequency: u32) -> i16 {
+    // 高频段（779 MHz 以上）和低频段的偏移不同
+    let offset = if frequency >= 779_000_000 { -157 } else { -164 };
+    if snr < 0.0 {
+        (offset as f32 + pkt_rssi as f32 + snr).round() as i16
+    } else {
+        offset + (pkt_rssi as i16 * 16 / 15)
+    }
+}
+
+/// 根据 REG_FEI_MSB ~ REG_FEI_LSB 计算频率误差，见 SX1276 数据手册 4.1.5
+pub fn frequency_error(fei: [u8; 3], bandwidth: u32) -> i32 {
+    // 20 位有符号数
+    let mut raw = (((fei[0] & 0x0F) as i32) << 16) | ((fei[1] as i32) << 8) | fei[2] as i32;
+    if raw & 0x80000 != 0 {
+        raw -= 0x100000;
+    }
+    // Ferr = FreqErr * 2^24 / Fxtal * BW / 500 kHz
+    (raw as f64 * (1 << 24) as f64 / 32_000_000.0 * bandwidth as f64 / 500_000.0).round() as i32
+}
+
+/// LoRa 射频参数，对应配置文件中的 `[data_if.lora]`，未配置的参数使用默认值：
+/// 434 MHz，Bw = 125 kHz，Cr = 4/8，Sf = 4096chips/symbol（SF12），前导码 8，同步字 0x12，13 dBm，CRC on。
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoraConfig {
+    // 频率，单位：Hz
+    pub frequency: u32,
+    // 带宽，单位：Hz
+    pub bandwidth: u32,
+    // 编码率 4/5 ~ 4/8，填分母
+    pub coding_rate: u8,
+    // 扩频因子 7 ~ 12
+    pub spreading_factor: u8,
+    pub preamble_length: u16,
+    pub sync_word: u8,
+    // 发射功率，单位：dBm
+    pub tx_power: u8,
+    pub crc: bool,
+    // 网关的 RadioHead 地址，下行报文的 from
+    pub address: u8,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        LoraConfig {
+            frequency: FREQ,
+            bandwidth: 125000,
+            coding_rate: 8,
+            spreading_factor: 12,
+            preamble_length: 8,
+            sync_word: 0x12,
+            tx_power: 13,
+            crc: true,
+            address: RH_BROADCAST_ADDRESS,
+        }
+    }
+}
+
+// SX1276 支持的频段（Band 3、Band 2、Band 1），单位：Hz
+const LORA_BANDS: [(u32, u32); 3] = [
+    (137_000_000, 175_000_000),
+    (410_000_000, 525_000_000),
+    (862_000_000, 1_020_000_000),
+];
+
+impl LoraConfig {
+    /// 检查参数是否在 SX1276 支持的范围内
+    pub fn check(&self) -> Result<(), String> {
+        if !LORA_BANDS.iter().any(|(low, high)| self.frequency >= *low && self.frequency <= *high) {
+            return Err(format!(
+                "invalid lora frequency: {} Hz, expected 137-175 MHz, 410-525 MHz or 862-1020 MHz", self.frequency
+            ));
+        }
+        if !BANDWIDTHS.contains(&self.bandwidth) {
+            return Err(format!("invalid lora bandwidth: {}, expected one of {:?} Hz", self.bandwidth, BANDWIDTHS));
+        }
+        if self.coding_rate < 5 || self.coding_rate > 8 {
+            return Err(format!("invalid lora coding_rate: {}, expected 5 to 8 (4/5 to 4/8)", self.coding_rate));
+        }
+        // SF6 只能用于隐式报头模式
+        if self.spreading_factor < 7 || self.spreading_factor > 12 {
+            return Err(format!("invalid lora spreading_factor: {}, expected 7 to 12", self.spreading_factor));
+        }
+        if self.preamble_length < 6 {
+            return Err(format!("invalid lora preamble_length: {}, expected at least 6", self.preamble_length));
+        }
+        if self.tx_power < 5 || self.tx_power > 23 {
+            return Err(format!("invalid lora tx_power: {}, expected 5 to 23 dBm", self.tx_power));
+        }
+        Ok(())
+    }
+
+    /// 发送 `len` 字节（包括报头）所需的时间，见 SX1276 数据手册 4.1.1.7
+    pub fn time_on_air(&self, len: usize) -> Duration {
+        let sf = self.spreading_factor as i64;
+        let symbol_us = (1_u64 << sf) as f64 * 1_000_000.0 / self.bandwidth as f64;
+        let de = if self.modem_config()[2] & LOW_DATA_RATE_OPTIMIZE != 0 { 1 } else { 0 };
+        let crc = if self.crc { 1 } else { 0 };
+        let numerator = 8 * len as i64 - 4 * sf + 28 + 16 * crc;
+        let denominator = 4 * (sf - 2 * de);
+        let symbols = 8 + ((numerator + denominator - 1) / denominator).max(0) * self.coding_rate as i64;
+        let preamble_us = (self.preamble_length as f64 + 4.25) * symbol_us;
+        Duration::from_micros((preamble_us + symbols as f64 * symbol_us) as u64)
+    }
+
+    /// REG_MODEM_CONFIG、REG_MODEM_CONFIG2、REG_MODEM_CONFIG3 的值
+    pub fn modem_config(&self) -> [u8; 3] {
+        let bw = BANDWIDTHS.iter().position(|bw| *bw == self.bandwidth).unwrap_or(7) as u8;
+        let config1 = (bw << 4) | ((self.coding_rate - 4) << 1);
+        let mut config2 = self.spreading_factor << 4;
+        if self.crc {
+            config2 |= 0x04;
+        }
+        // 符号时间超过 16 ms 时需要开启低速率优化
+        let mut config3 = 0x00;
+        if (1_u64 << self.spreading_factor) * 1_000_000 / self.bandwidth as u64 > 16_000 {
+            config3 |= LOW_DATA_RATE_OPTIMIZE;
+        }
+        [config1, config2, config3]
+    }
+}
+
+/// 以 `tail -F` 的方式跟踪文本文件，每读到一行（以 '\n' 结尾）产生一条消息。
+///
+/// 读取位置和文件的 inode 会保存到 `offset_file` 中，网关重启后从上次的位置继续读取，
+/// 文件被截断时从头开始读，文件被轮转（inode 变化）时读完旧文件后切换到新文件。
+#[derive(Debug)]
+pub struct FileIf {
+    path: String,
+    offset_file: String,
+    file: Option<File>,
+    inode: u64,
+    offset: u64,
+    partial: Vec<u8>,
+    saved: Option<(u64, u64)>,
+}
 
 impl FileIf {
-    pub fn read(self, filename: &str) -> Result<String, ()> {
-        match std::fs::read_to_string(filename) {
-            Ok(msg) => {
-                match std::fs::write(filename, "") {
-                    _ => {},
-                };
-                Ok(msg)
-            },
-            Err(_err) => Err(()),
+    pub fn new(path: &str, offset_file: &str) -> Self {
+        let saved = match std::fs::read_to_string(offset_file) {
+            Ok(s) => {
+                let mut iter = s.split_whitespace().map(|v| v.parse::<u64>());
+                match (iter.next(), iter.next()) {
+                    (Some(Ok(inode)), Some(Ok(offset))) => Some((inode, offset)),
+                    _ => {
+                        warn!("invalid offset file: {}", offset_file);
+                        None
+                    }
+                }
+            }
+            Err(_) => None,
+        };
+        FileIf {
+            path: path.to_string(),
+            offset_file: offset_file.to_string(),
+            file: None,
+            inode: 0,
+            offset: 0,
+            partial: Vec::new(),
+            saved,
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let file = File::open(&self.path)?;
+        let metadata = file.metadata()?;
+        self.inode = metadata.ino();
+        self.offset = 0;
+        // 仅在第一次打开文件时使用保存的读取位置
+        if let Some((inode, offset)) = self.saved.take() {
+            if inode == self.inode && offset <= metadata.len() {
+                self.offset = offset;
+            }
+        }
+        self.partial.clear();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn read_lines(&mut self, lines: &mut Vec<String>) -> io::Result<()> {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if file.metadata()?.len() < self.offset {
+            debug!("{} truncated", self.path);
+            self.offset = 0;
+            self.partial.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = Vec::new();
+        let n = file.read_to_end(&mut buf)?;
+        self.offset += n as u64;
+        for byte in buf {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.partial).trim().to_string();
+                if !line.is_empty() {
+                    lines.push(line);
+                }
+                self.partial.clear();
+            } else {
+                self.partial.push(byte);
+            }
+        }
+        Ok(())
+    }
+
+    fn save_offset(&self) {
+        // 保存最后一个完整行之后的位置，未读完的行在重启后重新读取
+        let offset = self.offset - self.partial.len() as u64;
+        if let Err(e) = std::fs::write(&self.offset_file, format!("{} {}\n", self.inode, offset)) {
+            warn!("save offset to {} failed: {}", self.offset_file, e);
+        }
+    }
+
+    pub fn read(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        if self.file.is_none() {
+            self.open()?;
+        }
+        self.read_lines(&mut lines)?;
+        // 文件被轮转：旧文件已读完，切换到新文件
+        if let Ok(metadata) = std::fs::metadata(&self.path) {
+            if metadata.ino() != self.inode {
+                debug!("{} rotated", self.path);
+                if !self.partial.is_empty() {
+                    warn!("discard incomplete line: {}", String::from_utf8_lossy(&self.partial));
+                }
+                self.open()?;
+                self.read_lines(&mut lines)?;
+            }
+        }
+        if !lines.is_empty() {
+            self.save_offset();
+        }
+        Ok(lines)
+    }
+}
+
+/// 将字节流按分隔符切分成行，处理跨多次读取的不完整行。
+///
+/// 超过 `max_len` 的行和不是 UTF-8 编码的行会被丢弃。
+pub struct LineBuffer {
+    delimiter: u8,
+    buf: Vec<u8>,
+    max_len: usize,
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub fn new(delimiter: u8, max_len: usize) -> Self {
+        LineBuffer {
+            delimiter,
+            buf: Vec::new(),
+            max_len,
+            overflow: false,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == self.delimiter {
+                if self.overflow {
+                    warn!("discard line longer than {} bytes", self.max_len);
+                } else {
+                    match std::str::from_utf8(&self.buf) {
+                        Ok(line) => {
+                            let line = line.trim();
+                            if !line.is_empty() {
+                                lines.push(line.to_string());
+                            }
+                        }
+                        Err(_) => warn!("discard invalid line: {:02x?}", self.buf),
+                    }
+                }
+                self.buf.clear();
+                self.overflow = false;
+            } else if self.buf.len() < self.max_len {
+                self.buf.push(byte);
+            } else {
+                self.overflow = true;
+            }
+        }
+        lines
+    }
+}
+
+// 按行读取时单行的默认最大长度
+pub const DEFAULT_MAX_LINE_LEN: usize = 1024;
+
+/// 按行读取的数据接口（serial_line、stdin、fifo）的行分隔符和单行最大长度，分隔符默认为 "lf"，不能为 "none"
+pub fn line_settings(if_type: &str, delimiter: Option<&str>, max_line_len: Option<usize>) -> Result<(u8, usize), String> {
+    let delimiter = parse_delimiter(delimiter.unwrap_or("lf"))?;
+    // 分隔符为 "crlf" 时按 '\n' 切分，行尾的 '\r' 会被去掉
+    let delimiter = match delimiter.last() {
+        Some(delimiter) => *delimiter,
+        None => return Err(format!("delimiter of {} can not be none", if_type)),
+    };
+    let max_line_len = max_line_len.unwrap_or(DEFAULT_MAX_LINE_LEN);
+    if max_line_len == 0 {
+        return Err(String::from("max_line_len must be greater than 0"));
+    }
+    Ok((delimiter, max_line_len))
+}
+
+/// 解析配置文件中的分隔符：`"lf"`、`"cr"`、`"crlf"`、`"none"`、十六进制（例如 `"0x03"`）或单个 ASCII 字符
+pub fn parse_delimiter(s: &str) -> Result<Vec<u8>, String> {
+    match s {
+        "lf" | "\n" => Ok(vec![b'\n']),
+        "cr" | "\r" => Ok(vec![b'\r']),
+        "crlf" | "\r\n" => Ok(vec![b'\r', b'\n']),
+        "none" | "" => Ok(Vec::new()),
+        _ => {
+            if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => Ok(vec![byte]),
+                    Err(_) => Err(format!("invalid delimiter: {}", s)),
+                }
+            } else if s.len() == 1 {
+                Ok(s.as_bytes().to_vec())
+            } else {
+                Err(format!("invalid delimiter: {}, expected \"lf\", \"cr\", \"crlf\", \"none\", a hex byte like \"0x03\" or a single character", s))
+            }
+        }
+    }
+}
+
+/// 串口参数，默认 115200 8N1，无流控，读超时 10 ms。
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout: Duration,
+}
+
+impl Default for SerialSettings {
+    fn default() -> Self {
+        SerialSettings {
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_millis(10),
+        }
+    }
+}
+
+impl SerialSettings {
+    /// 校验配置文件中的串口参数，未配置的参数使用默认值
+    pub fn new(baud_rate: Option<u32>, data_bits: Option<u8>, parity: Option<&str>, stop_bits: Option<u8>,
+        flow_control: Option<&str>, timeout_ms: Option<u64>) -> Result<Self, String> {
+        let mut settings = SerialSettings::default();
+        if let Some(baud_rate) = baud_rate {
+            if baud_rate == 0 {
+                return Err(String::from("baud_rate must be greater than 0"));
+            }
+            settings.baud_rate = baud_rate;
+        }
+        if let Some(data_bits) = data_bits {
+            settings.data_bits = match data_bits {
+                5 => DataBits::Five,
+                6 => DataBits::Six,
+                7 => DataBits::Seven,
+                8 => DataBits::Eight,
+                _ => return Err(format!("invalid data_bits: {}, expected 5, 6, 7 or 8", data_bits)),
+            };
+        }
+        if let Some(parity) = parity {
+            settings.parity = match parity {
+                "none" => Parity::None,
+                "odd" => Parity::Odd,
+                "even" => Parity::Even,
+                _ => return Err(format!("invalid parity: {}, expected \"none\", \"odd\" or \"even\"", parity)),
+            };
         }
+        if let Some(stop_bits) = stop_bits {
+            settings.stop_bits = match stop_bits {
+                1 => StopBits::One,
+                2 => StopBits::Two,
+                _ => return Err(format!("invalid stop_bits: {}, expected 1 or 2", stop_bits)),
+            };
+        }
+        if let Some(flow_control) = flow_control {
+            settings.flow_control = match flow_control {
+                "none" => FlowControl::None,
+                "software" => FlowControl::Software,
+                "hardware" => FlowControl::Hardware,
+                _ => return Err(format!("invalid flow_control: {}, expected \"none\", \"software\" or \"hardware\"", flow_control)),
+            };
+        }
+        if let Some(timeout_ms) = timeout_ms {
+            if timeout_ms == 0 {
+                return Err(String::from("timeout_ms must be greater than 0"));
+            }
+            settings.timeout = Duration::from_millis(timeout_ms);
+        }
+        Ok(settings)
+    }
+
+    pub fn open(&self, path: &str) -> serialport::Result<Box<dyn SerialPort>> {
+        serialport::new(path, self.baud_rate)
+            .data_bits(self.data_bits)
+            .parity(self.parity)
+            .stop_bits(self.stop_bits)
+            .flow_control(self.flow_control)
+            .timeout(self.timeout)
+            .open()
     }
 }
 
@@ -159,61 +599,122 @@ impl min::Interface for HwIf {
 }
 
 
-#[derive(Debug, Copy, Clone)]
-pub struct SpiIf;
+/// SX1276 的 DIO0 引脚。接收模式下映射为 RxDone，发送模式下映射为 TxDone，事件发生时产生上升沿
+pub struct Dio0 {
+    handle: LineEventHandle,
+}
 
-impl SpiIf {
-    fn read_register(self, spi: &mut Spidev, addr: u8) -> io::Result<u8> {
+impl Dio0 {
+    pub fn new(chip: &str, line: u32) -> Result<Self, gpio_cdev::Error> {
+        let handle = Chip::new(chip)?
+            .get_line(line)?
+            .events(LineRequestFlags::INPUT, EventRequestFlags::RISING_EDGE, "gw-dio0")?;
+        Ok(Dio0 { handle })
+    }
+
+    /// 等待上升沿，超时返回 false
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.handle.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ret = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        if ret < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(false);
+            }
+            return Err(err);
+        }
+        if ret == 0 {
+            return Ok(false);
+        }
+        match self.handle.get_event() {
+            Ok(_) => Ok(true),
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
+        }
+    }
+}
+
+/// 通过复位引脚复位 SX1276：拉低 1 ms 后拉高，等待 10 ms 芯片就绪
+pub fn reset_radio(chip: &str, line: u32) -> Result<(), gpio_cdev::Error> {
+    let handle = Chip::new(chip)?
+        .get_line(line)?
+        .request(LineRequestFlags::OUTPUT, 0, "gw-reset")?;
+    thread::sleep(Duration::from_millis(1));
+    handle.set_value(1)?;
+    thread::sleep(Duration::from_millis(10));
+    Ok(())
+}
+
+/// SX127x 的寄存器访问，`SpiIf` 通过它读写寄存器，测试时可以用模拟器代替 SPI 设备
+pub trait RegisterAccess {
+    fn read_register(&mut self, addr: u8) -> io::Result<u8>;
+    fn write_register(&mut self, addr: u8, value: u8) -> io::Result<()>;
+    /// 从 REG_FIFO_ADDR_PTR 开始连续写 FIFO
+    fn write_fifo(&mut self, data: &[u8]) -> io::Result<()> {
+        for byte in data {
+            self.write_register(REG_FIFO, *byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl RegisterAccess for Spidev {
+    fn read_register(&mut self, addr: u8) -> io::Result<u8> {
         let mut rx_buf = [0_u8; 2];
         let tx_buf = [addr & 0x7f, 0];
         let mut transfer = SpidevTransfer::read_write(&tx_buf, &mut rx_buf);
-        spi.transfer(&mut transfer)?;
+        self.transfer(&mut transfer)?;
 
         Ok(rx_buf[1])
     }
 
-    fn write_register(self, spi: &mut Spidev, addr: u8, value: u8) -> io::Result<()> {
-        spi.write(&[addr | 0x80, value])?;
+    fn write_register(&mut self, addr: u8, value: u8) -> io::Result<()> {
+        self.write_all(&[addr | 0x80, value])?;
 
         Ok(())
     }
 
-    fn set_modem_config(self, spi: &mut Spidev, config: ModemConfigChoice) -> io::Result<()> {
-        match config {
-            ModemConfigChoice::Bw125Cr45Sf128 => {
-                self.write_register(spi, REG_MODEM_CONFIG, 0x72)?;
-                self.write_register(spi, REG_MODEM_CONFIG2, 0x74)?;
-                self.write_register(spi, REG_MODEM_CONFIG3, 0x00)?;
-                Ok(())
-            }
-            ModemConfigChoice::Bw500Cr45Sf128 => {
-                self.write_register(spi, REG_MODEM_CONFIG, 0x92)?;
-                self.write_register(spi, REG_MODEM_CONFIG2, 0x74)?;
-                self.write_register(spi, REG_MODEM_CONFIG3, 0x00)?;
-                Ok(())
-            }
-            ModemConfigChoice::Bw31_25Cr48Sf512 => {
-                self.write_register(spi, REG_MODEM_CONFIG, 0x48)?;
-                self.write_register(spi, REG_MODEM_CONFIG2, 0x94)?;
-                self.write_register(spi, REG_MODEM_CONFIG3, 0x00)?;
-                Ok(())
-            }
-            ModemConfigChoice::Bw125Cr48Sf4096 => {
-                self.write_register(spi, REG_MODEM_CONFIG, 0x78)?;
-                self.write_register(spi, REG_MODEM_CONFIG2, 0xc4)?;
-                self.write_register(spi, REG_MODEM_CONFIG3, 0x08)?;
-                Ok(())
-            }
-        }
+    // 一次 SPI 传输写入所有数据
+    fn write_fifo(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(1 + data.len());
+        buf.push(REG_FIFO | 0x80);
+        buf.extend_from_slice(data);
+        self.write_all(&buf)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SpiIf;
+
+impl SpiIf {
+    fn read_register(self, spi: &mut dyn RegisterAccess, addr: u8) -> io::Result<u8> {
+        spi.read_register(addr)
     }
 
-    fn set_preamble_length(self, spi: &mut Spidev, length: u16) -> io::Result<()> {
+    fn write_register(self, spi: &mut dyn RegisterAccess, addr: u8, value: u8) -> io::Result<()> {
+        spi.write_register(addr, value)
+    }
+
+    fn set_modem_config(self, spi: &mut dyn RegisterAccess, config: &LoraConfig) -> io::Result<()> {
+        let [config1, config2, config3] = config.modem_config();
+        self.write_register(spi, REG_MODEM_CONFIG, config1)?;
+        self.write_register(spi, REG_MODEM_CONFIG2, config2)?;
+        self.write_register(spi, REG_MODEM_CONFIG3, config3)?;
+        Ok(())
+    }
+
+    fn set_preamble_length(self, spi: &mut dyn RegisterAccess, length: u16) -> io::Result<()> {
         self.write_register(spi, REG_PREAMBLE_MSB, (length >> 8) as u8)?;
         self.write_register(spi, REG_PREAMBLE_LSB, (length & 0xff) as u8)?;
         Ok(())
     }
 
-    fn set_frequency(self, spi: &mut Spidev, freq: u32) -> io::Result<()> {
+    fn set_frequency(self, spi: &mut dyn RegisterAccess, freq: u32) -> io::Result<()> {
         // Frf = FRF / FSTEP
         let frf: u64 = ((freq as u64) << 19) / 32000000;
         self.write_register(spi, REG_FRF_MSB, (frf >> 16) as u8)?;
@@ -223,7 +724,7 @@ impl SpiIf {
         Ok(())
     }
 
-    fn set_tx_power(self, spi: &mut Spidev, power: u8) -> io::Result<()> {
+    fn set_tx_power(self, spi: &mut dyn RegisterAccess, power: u8) -> io::Result<()> {
         let mut p = power;
         if power > 23 {
             p = 23;
@@ -246,14 +747,68 @@ impl SpiIf {
         Ok(())
     }
 
-    fn set_mode_rx(self, spi: &mut Spidev) -> io::Result<()> {
+    fn set_mode_rx(self, spi: &mut dyn RegisterAccess) -> io::Result<()> {
         self.write_register(spi, REG_OPMODE, SX72_MODE_RX_CONTINUOS)?;
         self.write_register(spi, REG_DIO_MAPPING1, 0x00)?;
 
         Ok(())
     }
 
-    fn setup_lora(self, spi: &mut Spidev) -> io::Result<()> {
+    fn set_mode_standby(self, spi: &mut dyn RegisterAccess) -> io::Result<()> {
+        self.write_register(spi, REG_OPMODE, SX72_MODE_STANDBY)?;
+
+        Ok(())
+    }
+
+    /// 发送一个报文：进入待机模式，将报头和数据写入 FIFO，进入发送模式，
+    /// 等待 TX_DONE 后回到接收模式。有 DIO0 时等待 DIO0 的上升沿，否则轮询 REG_IRQ_FLAGS。
+    /// 超过 `timeout` 仍未发送完成时返回 TimedOut。
+    pub fn send(self, spi: &mut dyn RegisterAccess, header: &RhHeader, payload: &[u8], timeout: Duration,
+        mut dio0: Option<&mut Dio0>) -> io::Result<()> {
+        if payload.len() > RH_MAX_PAYLOAD_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("payload too long: {} bytes, max {}", payload.len(), RH_MAX_PAYLOAD_LEN),
+            ));
+        }
+        self.set_mode_standby(spi)?;
+        self.write_register(spi, REG_FIFO_ADDR_PTR, 0)?;
+        let mut buf = Vec::with_capacity(RH_HEADER_LEN + payload.len());
+        buf.extend_from_slice(&header.to_bytes());
+        buf.extend_from_slice(payload);
+        spi.write_fifo(&buf)?;
+        self.write_register(spi, REG_PAYLOAD_LENGTH, (RH_HEADER_LEN + payload.len()) as u8)?;
+        // DIO0 映射为 TxDone
+        self.write_register(spi, REG_DIO_MAPPING1, 0x40)?;
+        self.write_register(spi, REG_OPMODE, SX72_MODE_TX)?;
+
+        let start = std::time::Instant::now();
+        let result = loop {
+            let irq_flags = self.read_register(spi, REG_IRQ_FLAGS)?;
+            if irq_flags & TX_DONE != 0 {
+                break Ok(());
+            }
+            let elapsed = start.elapsed();
+            if elapsed > timeout {
+                break Err(io::Error::new(io::ErrorKind::TimedOut, "wait for TX_DONE timeout"));
+            }
+            // 没有 DIO0 或者等待出错时轮询
+            let waited = match dio0.as_mut() {
+                Some(dio0) => dio0.wait(timeout - elapsed).is_ok(),
+                None => false,
+            };
+            if !waited {
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+        // Clear all IRQ flags
+        self.write_register(spi, REG_IRQ_FLAGS, 0xff)?;
+        self.set_mode_rx(spi)?;
+
+        result
+    }
+
+    fn setup_lora(self, spi: &mut dyn RegisterAccess, config: &LoraConfig) -> io::Result<()> {
         let version = self.read_register(spi, REG_VERSION)?;
         match version {
             0x22 => println!("SX1272 detected, starting."),
@@ -285,92 +840,238 @@ impl SpiIf {
 
         self.write_register(spi, REG_OPMODE, SX72_MODE_STANDBY)?;
 
-        // Bw = 125 kHz, Cr = 4/8, Sf = 4096chips/symbol, CRC on. Slow+long range
-        self.set_modem_config(spi, ModemConfigChoice::Bw125Cr48Sf4096)?;
+        self.set_modem_config(spi, config)?;
 
-        self.set_preamble_length(spi, 8)?;
-        self.set_frequency(spi, FREQ)?;
-        self.set_tx_power(spi, 13)?;
+        self.set_preamble_length(spi, config.preamble_length)?;
+        self.set_frequency(spi, config.frequency)?;
+        self.write_register(spi, REG_SYNC_WORD, config.sync_word)?;
+        self.set_tx_power(spi, config.tx_power)?;
 
         self.set_mode_rx(spi)?;
 
         Ok(())
     }
 
-    pub fn init(self, spi: &mut Spidev) -> io::Result<()> {
-        self.setup_lora(spi)?;
+    pub fn init(self, spi: &mut dyn RegisterAccess, config: &LoraConfig) -> io::Result<()> {
+        self.setup_lora(spi, config)?;
 
         Ok(())
     }
 
-    pub fn read(self, spi: &mut Spidev) -> Result<String, ()> {
-        let mut buffer: [u8; 256] = [0; 256];
-        if let Ok(_) = self.set_mode_rx(spi) {
-            if let Ok(irq_flags) = self.read_register(spi, REG_IRQ_FLAGS) {
-                if irq_flags & RX_DONE != 0 {
-                    if irq_flags & 0x20 == 0x20 {
-                        debug!("CRC error");
-                        Err(())
-                    } else {
-                        if let Ok(current_addr) = self.read_register(spi, REG_FIFO_RX_CURRENT_ADDR)
-                        {
-                            if let Ok(received_count) = self.read_register(spi, REG_RX_NB_BYTES) {
-                                if let Ok(_) =
-                                    self.write_register(spi, REG_FIFO_ADDR_PTR, current_addr)
-                                {
-                                    for i in 0..received_count as usize {
-                                        buffer[i] = match self.read_register(spi, REG_FIFO) {
-                                            Ok(b) => b,
-                                            Err(e) => {
-                                                debug!("{}", e);
-                                                0
-                                            }
-                                        }
-                                    }
-                                    // Clear all IRQ flags
-                                    match self.write_register(spi, REG_IRQ_FLAGS, 0xff) {
-                                        Ok(_) => {}
-                                        Err(e) => {
-                                            debug!("{}", e);
-                                        }
-                                    }
-                                    if let Ok(s) = String::from_utf8(
-                                        buffer[4..received_count as usize].to_vec(),
-                                    ) {
-                                        Ok(s)
-                                    } else {
-                                        debug!("get string error");
-                                        Err(())
-                                    }
-                                } else {
-                                    debug!(
-                                        "write REG_FIFO_ADDR_PTR(0x{:02X}) error",
-                                        REG_FIFO_ADDR_PTR
-                                    );
-                                    Err(())
-                                }
-                            } else {
-                                debug!("read REG_RX_NB_BYTES(0x{:02X}) error", REG_RX_NB_BYTES);
-                                Err(())
-                            }
-                        } else {
-                            debug!(
-                                "read REG_FIFO_RX_CURRENT_ADDR(0x{:02X}) error",
-                                REG_FIFO_RX_CURRENT_ADDR
-                            );
-                            Err(())
-                        }
-                    }
-                } else {
-                    Err(())
-                }
-            } else {
-                debug!("read REG_IRQ_FLAGS(0x{:02X}) error", REG_IRQ_FLAGS);
-                Err(())
+    fn read_packet(self, spi: &mut dyn RegisterAccess, config: &LoraConfig) -> io::Result<LoraPacket> {
+        let current_addr = self.read_register(spi, REG_FIFO_RX_CURRENT_ADDR)?;
+        let received_count = self.read_register(spi, REG_RX_NB_BYTES)? as usize;
+        self.write_register(spi, REG_FIFO_ADDR_PTR, current_addr)?;
+        let mut buffer = Vec::with_capacity(received_count);
+        for _ in 0..received_count {
+            buffer.push(self.read_register(spi, REG_FIFO)?);
+        }
+        let snr = self.read_register(spi, REG_PKT_SNR_VALUE)?;
+        let rssi = self.read_register(spi, REG_PKT_RSSI_VALUE)?;
+        let fei = [
+            self.read_register(spi, REG_FEI_MSB)?,
+            self.read_register(spi, REG_FEI_MID)?,
+            self.read_register(spi, REG_FEI_LSB)?,
+        ];
+        if buffer.len() < RH_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet too short: {} bytes", buffer.len()),
+            ));
+        }
+        let snr = snr as i8 as f32 / 4.0;
+        Ok(LoraPacket {
+            header: RhHeader {
+                to: buffer[0],
+                from: buffer[1],
+                id: buffer[2],
+                flags: buffer[3],
+            },
+            rssi: packet_rssi(rssi, snr, config.frequency),
+            snr,
+            freq_error: frequency_error(fei, config.bandwidth),
+            payload: buffer[RH_HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// 读取收到的报文，没有收到报文或者 CRC 错误时返回 Err
+    pub fn read(self, spi: &mut dyn RegisterAccess, config: &LoraConfig) -> Result<LoraPacket, ()> {
+        if let Err(e) = self.set_mode_rx(spi) {
+            debug!("set_mode_rx error: {}", e);
+            return Err(());
+        }
+        let irq_flags = match self.read_register(spi, REG_IRQ_FLAGS) {
+            Ok(irq_flags) => irq_flags,
+            Err(e) => {
+                debug!("read REG_IRQ_FLAGS(0x{:02X}) error: {}", REG_IRQ_FLAGS, e);
+                return Err(());
             }
-        } else {
-            debug!("set_mode_rx error");
+        };
+        if irq_flags & RX_DONE == 0 {
+            return Err(());
+        }
+        let result = if irq_flags & PAYLOAD_CRC_ERROR != 0 {
+            debug!("CRC error");
             Err(())
+        } else {
+            self.read_packet(spi, config).map_err(|e| debug!("read packet error: {}", e))
+        };
+        // Clear all IRQ flags
+        if let Err(e) = self.write_register(spi, REG_IRQ_FLAGS, 0xff) {
+            debug!("{}", e);
         }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interface::{frequency_error, line_settings, packet_rssi, parse_delimiter, FileIf, DEFAULT_MAX_LINE_LEN, LineBuffer, LoraConfig, SerialSettings};
+    use serialport::{FlowControl, Parity, StopBits};
+    use std::time::Duration;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn test_files(name: &str) -> (String, String) {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("gw_{}_{}.txt", name, std::process::id()));
+        let offset = dir.join(format!("gw_{}_{}.offset", name, std::process::id()));
+        let _ = std::fs::remove_file(&offset);
+        std::fs::write(&path, "").unwrap();
+        (path.to_str().unwrap().to_string(), offset.to_str().unwrap().to_string())
+    }
+
+    fn append(path: &str, data: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn file_if_follow() {
+        let (path, offset) = test_files("follow");
+        let mut file_if = FileIf::new(&path, &offset);
+        append(&path, "{\"t\": 1}\n{\"t\": 2");
+        assert_eq!(file_if.read().unwrap(), vec!["{\"t\": 1}".to_string()]);
+        append(&path, "}\n");
+        assert_eq!(file_if.read().unwrap(), vec!["{\"t\": 2}".to_string()]);
+        assert_eq!(file_if.read().unwrap().len(), 0);
+        // 截断后从头读取
+        std::fs::write(&path, "a\n").unwrap();
+        assert_eq!(file_if.read().unwrap(), vec!["a".to_string()]);
+        // 轮转后读取新文件
+        std::fs::rename(&path, format!("{}.1", path)).unwrap();
+        std::fs::write(&path, "b\n").unwrap();
+        assert_eq!(file_if.read().unwrap(), vec!["b".to_string()]);
+        let _ = std::fs::remove_file(format!("{}.1", path));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&offset);
+    }
+
+    #[test]
+    fn line_buffer_partial() {
+        let mut lines = LineBuffer::new(b'\n', 8);
+        assert_eq!(lines.push(b"{\"a\""), Vec::<String>::new());
+        assert_eq!(lines.push(b":1}\n{\"b\":2}\r\n"), vec!["{\"a\":1}", "{\"b\":2}"]);
+        // 超长的行被丢弃
+        assert_eq!(lines.push(b"0123456789\nok\n"), vec!["ok"]);
+    }
+
+    #[test]
+    fn line_buffer_delimiter() {
+        let mut lines = LineBuffer::new(b'\r', 64);
+        assert_eq!(lines.push(b"$GPGGA,1\r\n$GPGGA,2\r"), vec!["$GPGGA,1", "$GPGGA,2"]);
+        // 非 UTF-8 的行被丢弃
+        assert_eq!(lines.push(b"\xff\xfe\x00\rok\r"), vec!["ok"]);
+    }
+
+    #[test]
+    fn delimiter() {
+        assert_eq!(parse_delimiter("lf").unwrap(), b"\n".to_vec());
+        assert_eq!(parse_delimiter("crlf").unwrap(), b"\r\n".to_vec());
+        assert_eq!(parse_delimiter("0x03").unwrap(), vec![0x03]);
+        assert_eq!(parse_delimiter(";").unwrap(), b";".to_vec());
+        assert_eq!(parse_delimiter("none").unwrap(), Vec::<u8>::new());
+        assert!(parse_delimiter("0xzz").is_err());
+        assert!(parse_delimiter("abc").is_err());
+
+        assert_eq!(line_settings("serial_line", None, None).unwrap(), (b'\n', DEFAULT_MAX_LINE_LEN));
+        assert_eq!(line_settings("fifo", Some("crlf"), Some(64)).unwrap(), (b'\n', 64));
+        assert_eq!(line_settings("stdin", Some("none"), None).unwrap_err(), "delimiter of stdin can not be none");
+        assert!(line_settings("serial_line", Some("lf"), Some(0)).is_err());
+    }
+
+    #[test]
+    fn serial_settings() {
+        let settings = SerialSettings::new(Some(9600), Some(8), Some("even"), Some(2), None, Some(100)).unwrap();
+        assert_eq!(settings.baud_rate, 9600);
+        assert_eq!(settings.parity, Parity::Even);
+        assert_eq!(settings.stop_bits, StopBits::Two);
+        assert_eq!(settings.flow_control, FlowControl::None);
+        assert_eq!(settings.timeout, Duration::from_millis(100));
+        assert_eq!(SerialSettings::new(None, None, None, None, None, None).unwrap(), SerialSettings::default());
+        assert!(SerialSettings::new(None, Some(9), None, None, None, None).is_err());
+        assert!(SerialSettings::new(None, None, Some("mark"), None, None, None).is_err());
+        assert!(SerialSettings::new(None, None, None, Some(3), None, None).is_err());
+        assert!(SerialSettings::new(None, None, None, None, Some("rts"), None).is_err());
+    }
+
+    #[test]
+    fn file_if_resume() {
+        let (path, offset) = test_files("resume");
+        append(&path, "a\nb\nc");
+        let mut file_if = FileIf::new(&path, &offset);
+        assert_eq!(file_if.read().unwrap(), vec!["a".to_string(), "b".to_string()]);
+        // 模拟网关重启
+        append(&path, "\nd\n");
+        let mut file_if = FileIf::new(&path, &offset);
+        assert_eq!(file_if.read().unwrap(), vec!["c".to_string(), "d".to_string()]);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&offset);
+    }
+
+    #[test]
+    fn lora_config() {
+        let config = LoraConfig::default();
+        assert!(config.check().is_ok());
+        // 与原来的 Bw125Cr48Sf4096 相同
+        assert_eq!(config.modem_config(), [0x78, 0xc4, 0x08]);
+        let config = LoraConfig { frequency: 868_100_000, spreading_factor: 7, coding_rate: 5, ..LoraConfig::default() };
+        assert_eq!(config.modem_config(), [0x72, 0x74, 0x00]);
+        let config = LoraConfig { bandwidth: 500000, crc: false, ..config };
+        assert_eq!(config.modem_config(), [0x92, 0x70, 0x00]);
+        assert!(LoraConfig { frequency: 2_400_000_000, ..LoraConfig::default() }.check().is_err());
+        // 各频段的边界
+        for (frequency, valid) in [
+            (136_999_999, false), (137_000_000, true), (175_000_000, true), (175_000_001, false),
+            (409_999_999, false), (410_000_000, true), (525_000_000, true), (525_000_001, false),
+            (861_999_999, false), (862_000_000, true), (1_020_000_000, true), (1_020_000_001, false),
+        ].iter() {
+            assert_eq!(LoraConfig { frequency: *frequency, ..LoraConfig::default() }.check().is_ok(), *valid, "{}", frequency);
+        }
+        assert!(LoraConfig { bandwidth: 100000, ..LoraConfig::default() }.check().is_err());
+        assert!(LoraConfig { spreading_factor: 6, ..LoraConfig::default() }.check().is_err());
+        assert!(LoraConfig { tx_power: 30, ..LoraConfig::default() }.check().is_err());
+        let config: LoraConfig = toml::from_str("frequency = 915000000\nspreading_factor = 9").unwrap();
+        assert_eq!(config.frequency, 915_000_000);
+        assert_eq!(config.bandwidth, 125000);
+    }
+
+    #[test]
+    fn lora_packet_quality() {
+        assert_eq!(packet_rssi(60, 9.5, 868_100_000), -93);
+        assert_eq!(packet_rssi(60, -5.0, 434_000_000), -109);
+        // 0xFFF38 = -200
+        assert_eq!(frequency_error([0x0F, 0xFF, 0x38], 125_000), -26);
+        assert_eq!(frequency_error([0x00, 0x03, 0xE8], 500_000), 524);
+    }
+
+    #[test]
+    fn lora_time_on_air() {
+        // SF7、125 kHz、4/5、CRC on、前导码 8，10 字节约 41 ms
+        let config = LoraConfig { spreading_factor: 7, coding_rate: 5, ..LoraConfig::default() };
+        assert_eq!(config.time_on_air(10).as_micros(), 41216);
+        // SF12 开启低速率优化
+        let config = LoraConfig::default();
+        assert_eq!(config.time_on_air(10).as_millis(), 1187);
     }
 }
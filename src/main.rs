@@ -15,25 +15,36 @@ mod mqtt;
 mod types;
 mod interface;
 mod data_manager;
+mod socket_if;
+mod pipe_if;
+mod sysfs_if;
+mod data_source;
+mod modbus;
+mod can_if;
+mod min_message;
+mod decoder;
+mod struct_decoder;
+#[cfg(test)]
+mod sx127x_sim;
 
-use types::{ClientConfig, TopicConfig, TlsFiles, MsgReceiver};
+use types::{ClientConfig, TopicConfig, TlsFiles, MsgReceiver, DataIfConfig, DownstreamMsg, OriginalData, INTERFACE_CALLS};
+use data_source::{DataIfError, LogEvent, Registry};
+use decoder::PayloadDecoder;
+use min_message::{MinRouter, Routed};
 
 use chrono::{Local, DateTime};
 use data_manager::data_management::{data_base, DeviceData};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use shadow_rs::shadow;
-use clap::{App, Arg, crate_name, crate_version, crate_authors};
-use data_template::{Model, Template, Value};
+use clap::{App, Arg, ArgMatches, SubCommand, crate_name, crate_version, crate_authors};
+use data_template::{Call, CompiledTemplate, Value};
 use serde_derive::Deserialize;
-use serialport::SerialPort;
 use std::io::prelude::*;
 use std::sync::mpsc;
+use std::collections::HashMap;
 use std::{env, fs, str, thread};
 #[cfg(feature = "ssl")]
 use std::path::Path;
-use interface::{FileIf, HwIf, SpiIf};
-use std::fs::File;
-use spidev::{SpiModeFlags, Spidev, SpidevOptions};
 use log::{error, warn, info, debug, LevelFilter};
 use log4rs::{
     append::{
@@ -53,19 +64,6 @@ use log4rs::{
 
 shadow!(build);
 
-struct SensorInterface {
-    text_file: String,
-    serial_port: Option<Box<dyn SerialPort>>,
-    spi: Option<Spidev>,
-}
-
-#[derive(Debug)]
-enum DataIfError {
-    DataIfOpenError,
-    DataIfInitError,
-    DataIfUnknownType,
-}
-
 enum DbOp {
     INSERT,
     QUERY,
@@ -101,7 +99,7 @@ struct AppConfig {
     topic: TopicConfig,
     msg: MsgConfig,
     database: DatabaseConfig,
-    data_if: DataIfConfig,
+    data_if: DataIfConfigs,
 }
 
 #[derive(Deserialize)]
@@ -130,10 +128,21 @@ struct DatabaseConfig {
     name: String,
 }
 
+// 兼容单个数据接口（[data_if]）和多个数据接口（[[data_if]]）两种配置方式
 #[derive(Deserialize)]
-struct DataIfConfig {
-    if_name: String,
-    if_type: String,
+#[serde(untagged)]
+enum DataIfConfigs {
+    One(DataIfConfig),
+    Many(Vec<DataIfConfig>),
+}
+
+impl DataIfConfigs {
+    fn into_vec(self) -> Vec<DataIfConfig> {
+        match self {
+            DataIfConfigs::One(data_if) => vec![data_if],
+            DataIfConfigs::Many(data_ifs) => data_ifs,
+        }
+    }
 }
 
 fn init_app_log(log_config: &LogConfig) -> Result<(), ()> {
@@ -217,135 +226,167 @@ fn get_msg_from_data(data: &DeviceData) -> String {
     data.msg.clone()
 }
 
-fn format_msg(original: &str, template_str: &str) -> Result<String, ()> {
-    let parsed = match json::parse(&original) {
+// 数据模板在启动时编译，编译失败时退出
+fn compile_template(template: &str) -> CompiledTemplate {
+    match CompiledTemplate::new(template) {
+        Ok(compiled) => compiled,
+        Err(err) => panic!("invalid data template {}: {:?}", template, err),
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0_u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// 由网关提供值的调用模型：CLIENT_ID、HOSTNAME、UPTIME、SEQ、IF，其他的使用 data_template 内置的调用模型
+struct GatewayCalls {
+    client_id: String,
+    hostname: String,
+    start: Instant,
+    // 当前消息的序号，每条消息转换成功后加 1
+    seq: u64,
+}
+
+impl GatewayCalls {
+    fn new(client_id: &str, start: Instant) -> Self {
+        GatewayCalls {
+            client_id: client_id.to_string(),
+            hostname: hostname(),
+            start,
+            seq: 0,
+        }
+    }
+
+    fn call_result(&self, original: &OriginalData, call: &Call) -> Result<Value, data_template::Error> {
+        // 对端地址、LoRa 报文的 RSSI 等由数据接口提供，没有时替换为 null
+        if INTERFACE_CALLS.contains(&call.name.as_str()) {
+            return Ok(match (call.name.as_str(), &original.peer) {
+                ("PEER", Some(peer)) => Value::String(peer.clone()),
+                _ => match original.calls.get(&call.name) {
+                    Some(value) => Value::from(value),
+                    None => Value::Null,
+                },
+            });
+        }
+        let args: Vec<&str> = call.args.iter().map(|arg| arg.as_str()).collect();
+        match (call.name.as_str(), args.as_slice()) {
+            ("CLIENT_ID", []) => Ok(Value::String(self.client_id.clone())),
+            ("HOSTNAME", []) => Ok(Value::String(self.hostname.clone())),
+            // 网关启动以来的秒数，UPTIME(ms) 为毫秒数
+            ("UPTIME", []) | ("UPTIME", ["s"]) => Ok(Value::Number(self.start.elapsed().as_secs() as i64)),
+            ("UPTIME", ["ms"]) => Ok(Value::Number(self.start.elapsed().as_millis() as i64)),
+            ("SEQ", []) => Ok(Value::Number(self.seq as i64)),
+            ("IF", []) => Ok(Value::String(original.if_name.clone())),
+            _ => data_template::call_result(call),
+        }
+    }
+}
+
+// 校验数据模板使用的原始数据示例：数据接口配置了 example 时使用它，
+// 只配置了自己的 template 时不校验（msg.example 不一定有该模板的标签），否则使用 msg.example
+fn template_example<'a>(data_if: &'a DataIfConfig, msg_example: &'a str) -> Option<&'a str> {
+    match (&data_if.example, &data_if.template) {
+        (Some(example), _) => Some(example),
+        (None, Some(_)) => None,
+        (None, None) => Some(msg_example),
+    }
+}
+
+// 用原始数据示例转换一次，结果是 JSON 时模板可用
+fn check_template(example: &str, template: &CompiledTemplate, calls: &mut GatewayCalls) -> bool {
+    match format_msg(&OriginalData::new(example), template, calls) {
+        Ok(msg) => json::parse(&msg).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn format_msg(original: &OriginalData, template: &CompiledTemplate, gateway: &mut GatewayCalls) -> Result<String, ()> {
+    let parsed = match json::parse(&original.msg) {
         Ok(parsed) => parsed,
         Err(_err) => return Err(()),
     };
-    let template = Template::new(template_str);
-    let mut msg = template_str.to_string();
-    match template.get_value_models() {
-        Ok(value_models) => {
-            for model in value_models {
-                match model.get_label() {
-                    Ok(label) => {
-                        //replace
-                        let value_model = match model {
-                            Model::Value(value_model) => value_model,
-                        };
-                        if parsed[&label].is_string() {
-                            let string = match parsed[&label].as_str() {
-                                Some(string) => string,
-                                None => "",
-                            };
-                            msg = msg.replace(&value_model, &("\"".to_owned() + &string + "\""));
-                        } else if parsed[&label].is_number() {
-                            let num = match parsed[&label].as_number() {
-                                Some(num) => num,
-                                None => {
-                                    warn!("parse JSON number failed");
-                                    return Err(());
-                                }
-                            };
-                            msg = msg.replace(&value_model, &num.to_string());
-                        } else {
-                            warn!("unsurported data type");
-                        }
-                    }
-                    Err(err) => {
-                        warn!("get label from model {:#?} failed: {:#?}", model, err);
-                    }
-                }
-            }
+    // 标签可以是嵌套的路径，例如 <{sensors.temp[0].value}>、<{meta["device-id"]}>，字符串会按 JSON 转义
+    let rendered = template.render(&parsed, |call| gateway.call_result(original, call));
+    let msg = match rendered {
+        Ok(msg) => msg,
+        Err(data_template::Error::MissingPath(path)) => {
+            error!("{} not found in data: {}", path, original.msg);
+            return Err(());
         }
-        Err(_err) => {}
-    }
-    match template.get_call_models() {
-        Ok(call_models) => {
-            for model in call_models {
-                match model.get_call_result() {
-                    Ok(value) => {
-                        let call_model = match model {
-                            Model::Value(call_model) => call_model,
-                        };
-                        match value {
-                            Value::Number(num) => {
-                                msg = msg.replace(&call_model, &num.to_string());
-                            }
-                            Value::String(string) => {
-                                msg = msg.replace(&call_model, &string);
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        error!("get call result failed: {:#?}", err);
-                    }
-                }
-            }
+        Err(err) => {
+            error!("format msg with template {} failed: {:#?}", template.as_str(), err);
+            return Err(());
         }
-        Err(_err) => {}
-    }
+    };
     if let Err(_err) = json::parse(&msg) {
         error!("msg converted was not a JSON string: {}", msg);
         return Err(());
     }
+    gateway.seq += 1;
     Ok(msg)
 }
 
-fn init_data_interface(if_name: &str, if_type: &str) -> Result<SensorInterface, DataIfError> {
-    if if_type.eq("serial_port") {
-        let port = match serialport::new(if_name, 115200)
-            .timeout(Duration::from_millis(10))
-            .open()
-        {
-            Ok(port) => port,
+// 从数据接口读取原始数据，标记数据接口名称后发送给原始数据处理线程，并将下行消息发送给数据接口。
+// 数据接口在该线程内创建和打开，打开结果通过 open_result_tx 返回。
+// 数据接口产生的日志事件封装成 MQTT 消息后通过 log_tx 发布到 log_topic。
+fn original_data_read_closure(name: String, data_if: DataIfConfig, registry: Registry,
+    open_result_tx: mpsc::Sender<Result<(), DataIfError>>,
+    original_data_tx: mpsc::Sender<OriginalData>, downstream_msg_rx: mpsc::Receiver<DownstreamMsg>,
+    log_tx: mpsc::Sender<paho_mqtt::Message>, log_topic: String, qos: i32
+) -> impl FnOnce() -> () {
+    move || {
+        let mut source = match registry.build(&data_if).and_then(|mut source| source.open().map(|_| source)) {
+            Ok(source) => {
+                match open_result_tx.send(Ok(())) {
+                    _ => {}
+                }
+                source
+            }
             Err(err) => {
-                error!("Open {} failed: {}", if_name, err);
-                return Err(DataIfError::DataIfOpenError);
+                match open_result_tx.send(Err(err)) {
+                    _ => {}
+                }
+                return;
             }
         };
-        return Ok(SensorInterface{ text_file: if_name.to_string(), serial_port: Some(port), spi: None});
-    } else if if_type.eq("text_file") {
-        match File::open(if_name) {
-            Ok(_file) => return Ok(SensorInterface{ text_file: if_name.to_string(), serial_port: None, spi: None}),
-            Err(_err) => return Err(DataIfError::DataIfOpenError),
-        }
-    } else if if_type.eq("spi_sx1276") {
-        let spi = match Spidev::open(if_name) {
-            Ok(mut spi) => {
-                let options = SpidevOptions::new()
-                    .bits_per_word(8)
-                    .max_speed_hz(1000_000)
-                    .mode(SpiModeFlags::SPI_MODE_0)
-                    .build();
-                match spi.configure(&options) {
-                    Ok(_) => {
-                        if let Ok(_) = SpiIf.init(&mut spi) {
-                            spi
-                        } else {
-                            error!("init spi device failed: {}", if_name);
-                            return Err(DataIfError::DataIfOpenError);
+        let mut health = source.health();
+        loop {
+            // 每次轮询发送所有积压的下行消息
+            while let Ok(msg) = downstream_msg_rx.try_recv() {
+                info!("{}: {}", msg.topic, msg.payload);
+                if let Err(err) = source.write_to(&msg.topic, &msg.payload) {
+                    error!("Send msg to interface {} failed: {:?}", name, err);
+                }
+            }
+            match source.read() {
+                Ok(msgs) => {
+                    for mut data in msgs {
+                        data.if_name = name.clone();
+                        match original_data_tx.send(data) {
+                            _ => {}
                         }
                     }
-                    Err(err) => {
-                        error!("Open {} failed: {}", if_name, err);
-                        return Err(DataIfError::DataIfOpenError);
-                    }
                 }
+                Err(err) => debug!("read from interface {} failed: {:?}", name, err),
             }
-            Err(err) => {
-                error!("Open {} failed: {}", if_name, err);
-                return Err(DataIfError::DataIfOpenError);
+            for event in source.log_events() {
+                let log = format_log_event(&name, &event);
+                if let Err(err) = log_tx.send(paho_mqtt::Message::new(log_topic.clone(), log, qos)) {
+                    error!("Send log event of interface {} failed: {}", name, err);
+                }
             }
-        };
-        return Ok(SensorInterface {
-            text_file: if_name.to_string(),
-            serial_port: None,
-            spi: Some(spi),
-        });
-    } else {
-        error!("DataIf type unknown: {}", if_type);
-        return Err(DataIfError::DataIfUnknownType);
+            let current = source.health();
+            if current != health {
+                warn!("data interface {} is {}", name, current);
+                health = current;
+            }
+            thread::sleep(source.poll_interval());
+        }
     }
 }
 
@@ -363,7 +404,79 @@ fn format_log(msg: &str) -> Result<String, ()> {
     Ok(log)
 }
 
+// 格式化数据接口的日志事件，在 LOGS 的基础上附带事件类型、数据接口名称和事件的字段
+fn format_log_event(name: &str, event: &LogEvent) -> String {
+    let local: DateTime<Local> = Local::now(); // 本地时间
+    let time_str = local.format("%Y-%m-%d %H:%M:%S").to_string();
+    let mut log = event.fields.clone();
+    if !log.is_object() {
+        log = json::JsonValue::new_object();
+    }
+    log["LOGS"] = format!("[{}]: {}: {}", time_str, name, event.message).into();
+    log["event"] = event.event.clone().into();
+    log["data_if"] = name.into();
+    let log = log.dump();
+    info!("{}", log);
+    log
+}
+
+// decode 子命令：用数据接口配置的解码器解码抓取到的十六进制数据，输出解码得到的 JSON 和按数据模板转换后的消息，
+// 用于核对设备端的结构体布局。没有指定 --data-if 时使用第一个数据接口
+fn decode_command(config: AppConfig, matches: &ArgMatches) -> Result<(), String> {
+    let data_ifs = config.data_if.into_vec();
+    let data_if = match matches.value_of("DATA_IF") {
+        Some(name) => data_ifs.iter()
+            .find(|data_if| data_if.name.as_deref().unwrap_or(&data_if.if_type) == name)
+            .ok_or_else(|| format!("data interface not found: {}", name))?,
+        None => data_ifs.first().ok_or("no data interface in config-file")?,
+    };
+    let name = data_if.name.clone().unwrap_or_else(|| data_if.if_type.clone());
+    let hex = match matches.value_of("HEX") {
+        Some(hex) => hex.to_string(),
+        None => {
+            let mut hex = String::new();
+            std::io::stdin().read_to_string(&mut hex).map_err(|err| err.to_string())?;
+            hex
+        }
+    };
+    let payload = decoder::parse_hex(&hex)?;
+    let payload_decoder = PayloadDecoder::from_config(data_if).map_err(|err| err.to_string())?
+        .unwrap_or(PayloadDecoder::Json);
+    let mut data = match matches.value_of("MIN_ID") {
+        Some(min_id) => {
+            if data_if.if_type != "serial_port" {
+                return Err(format!("--min-id is not supported by {}", data_if.if_type));
+            }
+            let min_id = match min_id.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => min_id.parse(),
+            };
+            let min_id = min_id.map_err(|err| format!("invalid min id: {}", err))?;
+            let router = MinRouter::new(data_if.min_message.as_deref().unwrap_or(&[]), payload_decoder)?;
+            match router.route(min_id, &payload)? {
+                Routed::Data(data) => data,
+                Routed::Log(event) => {
+                    println!("{}", format_log_event(&name, &event));
+                    return Ok(());
+                }
+            }
+        }
+        None => OriginalData::new(&payload_decoder.decode(&payload)?.dump()),
+    };
+    data.if_name = name;
+    println!("{}", data.msg);
+    let template = data.template.clone()
+        .or_else(|| data_if.template.clone())
+        .unwrap_or(config.msg.template);
+    let compiled = CompiledTemplate::new(&template).map_err(|err| format!("invalid data template {}: {:?}", template, err))?;
+    let mut gateway = GatewayCalls::new(&config.client.id, Instant::now());
+    let msg = format_msg(&data, &compiled, &mut gateway).map_err(|_| format!("format msg with template failed: {}", template))?;
+    println!("{}", msg);
+    Ok(())
+}
+
 fn main() {
+    let start = Instant::now();
     env::set_var(
         "RUST_LOG",
         env::var_os("RUST_LOG").unwrap_or_else(|| "info".into()),
@@ -381,11 +494,40 @@ fn main() {
                 .required(true)
                 .help("specify the broker config file."),
         )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about("decode a captured hex dump with the decoder of a data interface, then apply its template.")
+                .arg(
+                    Arg::with_name("DATA_IF")
+                        .short("n")
+                        .long("data-if")
+                        .takes_value(true)
+                        .help("name of the data interface, defaults to the first one."),
+                )
+                .arg(
+                    Arg::with_name("MIN_ID")
+                        .short("m")
+                        .long("min-id")
+                        .takes_value(true)
+                        .help("MIN ID of the frame (serial_port only)."),
+                )
+                .arg(
+                    Arg::with_name("HEX")
+                        .help("payload in hex, read from stdin if omitted."),
+                ),
+        )
         .get_matches();
 
     let config_file = matches.value_of("CONFIG_FILE").unwrap();
     let toml_string = fs::read_to_string(&config_file).unwrap();
     let config: AppConfig = toml::from_str(&toml_string).unwrap();
+    if let Some(decode_matches) = matches.subcommand_matches("decode") {
+        if let Err(err) = decode_command(config, decode_matches) {
+            eprintln!("decode failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
     let app_log = config.log;
     let server_addr = config.server.address;
     #[cfg(feature = "ssl")]
@@ -398,25 +540,31 @@ fn main() {
     let database_name = config.database.name;
     let template = config.msg.template;
     let msg_example = config.msg.example;
-    let data_if_name = config.data_if.if_name;
-    let data_if_type = config.data_if.if_type;
+    let data_if_configs = config.data_if.into_vec();
 
     init_app_log(&app_log).unwrap();
 
-    // 数据模板校验
-    let check = match format_msg(&msg_example, &template) {
-        Ok(string) => string,
-        Err(err) => panic!(
-            "please check msg.example and msg.template if config-file: {:#?}",
-            err
-        ),
-    };
-    if let Err(_) = json::parse(&check) {
-        panic!(
-            "please check msg.example and msg.template config-file: {}",
-            config_file
-        );
+    // 数据接口名称不能重复，每个数据接口可以有自己的数据模板
+    let mut templates: HashMap<String, CompiledTemplate> = HashMap::new();
+    // 数据模板校验，不占用消息的序号
+    let mut check_calls = GatewayCalls::new(&client.id, start);
+    for data_if in data_if_configs.iter() {
+        let name = data_if.name.clone().unwrap_or_else(|| data_if.if_type.clone());
+        if templates.contains_key(&name) {
+            panic!("duplicate data interface name: {}, please set data_if.name in config-file: {}", name, config_file);
+        }
+        let if_template = compile_template(data_if.template.as_deref().unwrap_or(&template));
+        if let Some(example) = template_example(data_if, &msg_example) {
+            if !check_template(example, &if_template, &mut check_calls) {
+                panic!(
+                    "please check the example and the template of data interface {} in config-file: {}",
+                    name, config_file
+                );
+            }
+        }
+        templates.insert(name, if_template);
     }
+    let template = compile_template(&template);
 
     // 检查证书和密钥库是否存在
     #[cfg(feature = "ssl")]
@@ -432,10 +580,12 @@ fn main() {
         }
     }
 
-    let sensor_if = match init_data_interface(&data_if_name, &data_if_type) {
-        Ok(sensor_if) => sensor_if,
-        Err(err) => panic!("Init data interface failed: {:#?}", err),
-    };
+    let registry = Registry::default();
+    for data_if in data_if_configs.iter() {
+        if let Err(err) = registry.check(data_if) {
+            panic!("Init data interface {} failed: {}", data_if.if_name, err);
+        }
+    }
 
     let db = match init_data_base(&database_path, &database_name) {
         Ok(database) => database,
@@ -448,84 +598,61 @@ fn main() {
     let db_delete_req_tx = mpsc::Sender::clone(&insert_req);
     let (db_delete_rep_tx, db_delete_rep_rx) = mpsc::channel();
 
-    let original_data_pub_template = template.clone();
-
     let (original_data_tx, original_data_rx) = mpsc::channel();
 
     // 下行消息收发
-    let (downstream_msg_tx, downstream_msg_rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel();
+    let (downstream_msg_tx, downstream_msg_rx): (mpsc::Sender<paho_mqtt::Message>, mpsc::Receiver<paho_mqtt::Message>) = mpsc::channel();
+    let mut downstream_msg_txs: Vec<(String, mpsc::Sender<DownstreamMsg>)> = Vec::new();
 
-    // 获取原始数据
-    let original_data_read_thread_builder = thread::Builder::new().name("original_data_read_thread".into());
+    // 该通道用于将封装好的 MQTT 消息发送给数据上传线程
+    let (mqtt_message_sender, mqtt_message_receiver): (mpsc::Sender<paho_mqtt::Message>, mpsc::Receiver<paho_mqtt::Message>) = mpsc::channel();
+
+    // 每个数据接口一个线程获取原始数据
+    let mut original_data_read_threads = Vec::new();
+    for data_if in data_if_configs {
+        let name = data_if.name.clone().unwrap_or_else(|| data_if.if_type.clone());
+        let if_name = data_if.if_name.clone();
+        let (downstream_msg_tx, downstream_msg_rx) = mpsc::channel();
+        downstream_msg_txs.push((name.clone(), downstream_msg_tx));
+        let (open_result_tx, open_result_rx) = mpsc::channel();
+        let original_data_read_thread = thread::Builder::new()
+            .name(format!("data_if_{}", name))
+            .spawn(original_data_read_closure(name, data_if, registry.clone(), open_result_tx,
+                original_data_tx.clone(), downstream_msg_rx, mqtt_message_sender.clone(),
+                topic.pub_log_topic.clone(), topic.qos))
+            .unwrap();
+        match open_result_rx.recv() {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => panic!("Init data interface {} failed: {}", if_name, err),
+            Err(err) => panic!("Init data interface {} failed: {}", if_name, err),
+        }
+        original_data_read_threads.push(original_data_read_thread);
+    }
 
-    let original_data_read_thread = original_data_read_thread_builder
+    // 下行消息分发：主题中有某一级与数据接口名称相同时只发给该数据接口（例如 ctrl/uart/1），否则发给所有数据接口
+    let downstream_msg_router_builder = thread::Builder::new().name("downstream_msg_router".into());
+    let downstream_msg_router = downstream_msg_router_builder
         .spawn(move || {
-            let mut buf: Vec<u8> = (0..255).collect();
-            if data_if_type.eq("serial_port") {
-                if let Some(port) = sensor_if.serial_port {
-                    let uart = HwIf::new(port, String::from("uart"), 128);
-                    let mut min = min::Context::new(
-                        String::from("min"),
-                        &uart,
-                        0,
-                        false,
-                    );
-                    loop {
-                        if let Ok(msg) = downstream_msg_rx.try_recv() {
-                            info!("{}", msg);
-                            if let Err(_) = min.send_frame(0, msg.as_bytes(), msg.len() as u8)
-                            {
-                                error!("Send msg to interface failed.");
-                            }
-                        }
-                        if let Ok(n) = min.hw_if.read(&mut buf[..]) {
-                            min.poll(&buf[0..n], n as u32);
-                        };
-                        if let Ok(msg) = min.get_msg() {
-                            if let Ok(string) = String::from_utf8(msg.buf[0..msg.len as usize].to_vec()) {
-                                match original_data_tx.send(string) {
-                                    _ => {}
-                                }
-                            }
-                        }
-                        thread::sleep(Duration::from_millis(100));
-                    }
+            for msg in downstream_msg_rx.iter() {
+                let downstream_msg = DownstreamMsg {
+                    topic: msg.topic().to_string(),
+                    payload: String::from(msg.payload_str()),
                 };
-            } else if data_if_type.eq("spi_sx1276") {
-                if let Some(mut spi) = sensor_if.spi {
-                    loop {
-                        if let Ok(msg) = downstream_msg_rx.try_recv() {
-                            info!("{}", msg);
+                let mut routed = false;
+                for (name, tx) in downstream_msg_txs.iter() {
+                    if msg.topic().split('/').any(|level| level == name) {
+                        if let Err(err) = tx.send(downstream_msg.clone()) {
+                            error!("Send downstream msg to {} failed: {}", name, err);
                         }
-                        match SpiIf.read(&mut spi) {
-                            Ok(sn_msg) => {
-                                if !sn_msg.is_empty() {
-                                    match original_data_tx.send(String::from(sn_msg.trim())) {
-                                        _ => {}
-                                    }
-                                }
-                            }
-                            Err(_err) => continue,
-                        }
-                        thread::sleep(Duration::from_millis(100));
+                        routed = true;
                     }
                 }
-            } else {
-                loop {
-                    if let Ok(msg) = downstream_msg_rx.try_recv() {
-                        info!("{}", msg);
-                    }
-                    match FileIf.read(&sensor_if.text_file) {
-                        Ok(sn_msg) => {
-                            if !sn_msg.is_empty() {
-                                match original_data_tx.send(String::from(sn_msg.trim())) {
-                                    _ => {}
-                                }
-                            }
+                if !routed {
+                    for (name, tx) in downstream_msg_txs.iter() {
+                        if let Err(err) = tx.send(downstream_msg.clone()) {
+                            error!("Send downstream msg to {} failed: {}", name, err);
                         }
-                        Err(_err) => continue,
                     }
-                    thread::sleep(Duration::from_secs(1));
                 }
             }
         })
@@ -540,9 +667,6 @@ fn main() {
     // 该通道用于向数据发送者返回数据上传结果
     let (publish_result_sender, publish_result_receiver) = mpsc::channel();
 
-    // 该通道用于将封装好的 MQTT 消息发送给数据上传线程
-    let (mqtt_message_sender, mqtt_message_receiver): (mpsc::Sender<paho_mqtt::Message>, mpsc::Receiver<paho_mqtt::Message>) = mpsc::channel();
-
     // 通过该通道向所有需要获知网络连接状态的线程发送网络连通或断开消息（连通：Some(0)，断开：None）
     let (cloud_statue_announcement_sender, cloud_statue_announcement_receiver) = mpsc::channel();
     let cloud_statue_announcement_sender_clone = cloud_statue_announcement_sender.clone();
@@ -551,30 +675,52 @@ fn main() {
     let send_cloud_link_change_msg_to_data_manager = original_datum_sender.clone();
 
     // 原始数据处理
+    let client_id = client.id.clone();
     let original_data_handle_thread_builder =
         thread::Builder::new().name("original_data_handle_thread".into());
     let original_data_handle_thread = original_data_handle_thread_builder
-        .spawn(move || loop {
-            match original_data_rx.recv() {
-                Ok(sn_msg) => {
-                    match format_msg(&sn_msg, &original_data_pub_template) {
-                        Ok(formated_msg) => {
-                            match buffed_datum_sender.send(Datum {
-                                id: 0,
-                                datum_type: DatumType::Message,
-                                value: DeviceData::new(&formated_msg),
-                            }) {
-                                Err(err) => error!("send datum to data_manger failed: {}", err),
-                                _ => {}
+        .spawn(move || {
+            let mut gateway = GatewayCalls::new(&client_id, start);
+            // MIN ID 等为单条数据指定的数据模板，第一次使用时编译
+            let mut data_templates: HashMap<String, CompiledTemplate> = HashMap::new();
+            loop {
+                match original_data_rx.recv() {
+                    Ok(sn_msg) => {
+                        let template = match (&sn_msg.template, templates.get(&sn_msg.if_name)) {
+                            (Some(data_template), _) => {
+                                if !data_templates.contains_key(data_template) {
+                                    match CompiledTemplate::new(data_template) {
+                                        Ok(compiled) => data_templates.insert(data_template.clone(), compiled),
+                                        Err(err) => {
+                                            error!("invalid data template {}: {:?}", data_template, err);
+                                            continue;
+                                        }
+                                    };
+                                }
+                                &data_templates[data_template]
                             }
-                        }
-                        Err(_) => {
-                            error!("convert from data template failed: {}", sn_msg);
-                            continue;
-                        }
-                    };
+                            (None, Some(template)) => template,
+                            (None, None) => &template,
+                        };
+                        match format_msg(&sn_msg, template, &mut gateway) {
+                            Ok(formated_msg) => {
+                                match buffed_datum_sender.send(Datum {
+                                    id: 0,
+                                    datum_type: DatumType::Message,
+                                    value: DeviceData::new(&formated_msg),
+                                }) {
+                                    Err(err) => error!("send datum to data_manger failed: {}", err),
+                                    _ => {}
+                                }
+                            }
+                            Err(_) => {
+                                error!("convert from data template failed: {}", sn_msg.msg);
+                                continue;
+                            }
+                        };
+                    }
+                    Err(_err) => {}
                 }
-                Err(_err) => {}
             }
         })
         .unwrap();
@@ -851,7 +997,10 @@ fn main() {
         .spawn(mqtt::closure::sub_closure(rx, downstream_msg_tx, cloud_statue_announcement_sender))
         .unwrap();
 
-    original_data_read_thread.join().unwrap();
+    for original_data_read_thread in original_data_read_threads {
+        original_data_read_thread.join().unwrap();
+    }
+    downstream_msg_router.join().unwrap();
     original_data_handle_thread.join().unwrap();
     offine_data_handle_thread.join().unwrap();
     db_handle_thread.join().unwrap();
@@ -860,3 +1009,28 @@ fn main() {
     mqtt_pub_thread.join().unwrap();
     mqtt_sub_thread.join().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn data_if_template_example() {
+        let msg_example = "{\"l\":\"SN-004\",\"t\": 27.45}";
+        let config: DataIfConfig = toml::from_str("if_name = \"/dev/ttyS1\"\nif_type = \"serial_port\"").unwrap();
+        assert_eq!(template_example(&config, msg_example), Some(msg_example));
+        let mut calls = GatewayCalls::new("gw", Instant::now());
+        let template = "{<{node}>: {\"v\": <{v}>, \"ts\": <#TS#>}}";
+        // 数据接口自己的模板使用 msg.example 中没有的标签，不校验
+        let mut config: DataIfConfig = toml::from_str("if_name = \"0.0.0.0:5000\"\nif_type = \"udp\"").unwrap();
+        config.template = Some(template.to_string());
+        assert_eq!(template_example(&config, msg_example), None);
+        let template = compile_template(template);
+        assert!(!check_template(msg_example, &template, &mut calls));
+        // 配置了 example 时用它校验
+        config.example = Some(String::from("{\"node\": \"n1\", \"v\": 1.5}"));
+        let example = template_example(&config, msg_example).unwrap();
+        assert!(check_template(example, &template, &mut calls));
+        assert!(!check_template("{\"node\": \"n1\"}", &template, &mut calls));
+    }
+}
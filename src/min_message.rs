@@ -0,0 +1,262 @@
+//! MIN 帧 ID 与消息类型的映射，对应 `[[data_if.min_message]]`。
+//!
+//! 每个 MIN ID 可以指定消息类型、载荷解码器和数据模板：telemetry、event、binary 解码后按数据模板转换并发布，
+//! log、ack 作为日志事件发布到 `pub_log_topic`。下行消息的主题中有一级与 `downlink` 相同时以该 MIN ID 发送。
+
+use std::collections::HashMap;
+use serde_derive::Deserialize;
+use crate::data_source::LogEvent;
+use crate::decoder::PayloadDecoder;
+use crate::struct_decoder::StructFieldConfig;
+use crate::types::OriginalData;
+
+// MIN ID 只有 6 位（ID/控制字节的低 6 位），RESET、ACK 使用 0xFE、0xFF，不占用应用 ID
+pub const MAX_MIN_ID: u8 = 0x3F;
+pub const DEFAULT_DOWNLINK_MIN_ID: u8 = 0;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    #[default]
+    Telemetry,
+    Event,
+    Ack,
+    Log,
+    Binary,
+}
+
+impl MessageKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::Telemetry => "telemetry",
+            MessageKind::Event => "event",
+            MessageKind::Ack => "ack",
+            MessageKind::Log => "log",
+            MessageKind::Binary => "binary",
+        }
+    }
+
+    fn default_decoder(&self, telemetry: &PayloadDecoder) -> PayloadDecoder {
+        match self {
+            MessageKind::Telemetry | MessageKind::Event => telemetry.clone(),
+            MessageKind::Ack | MessageKind::Log => PayloadDecoder::Text,
+            MessageKind::Binary => PayloadDecoder::Hex,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MinMessageConfig {
+    pub min_id: u8,
+    #[serde(default)]
+    pub kind: MessageKind,
+    // 解码器，默认 telemetry、event 使用数据接口的解码器（没有配置时为 json），ack、log 为 text，binary 为 hex
+    pub decoder: Option<String>,
+    // decoder 为 struct 时的结构体布局，对应 [[data_if.min_message.struct_field]]
+    pub struct_field: Option<Vec<StructFieldConfig>>,
+    // 该 MIN ID 使用的数据模板，默认使用数据接口的数据模板
+    pub template: Option<String>,
+    // 下行主题中有一级与之相同时以该 MIN ID 发送
+    pub downlink: Option<String>,
+}
+
+/// 按 MIN ID 处理收到的帧的结果
+#[derive(Debug)]
+pub enum Routed {
+    Data(OriginalData),
+    Log(LogEvent),
+}
+
+struct MinMessage {
+    kind: MessageKind,
+    decoder: PayloadDecoder,
+    template: Option<String>,
+}
+
+pub struct MinRouter {
+    messages: HashMap<u8, MinMessage>,
+    downlinks: Vec<(String, u8)>,
+    // telemetry、event 和没有配置的 MIN ID 默认使用的解码器
+    decoder: PayloadDecoder,
+}
+
+impl MinRouter {
+    pub fn new(configs: &[MinMessageConfig], decoder: PayloadDecoder) -> Result<Self, String> {
+        let mut messages = HashMap::new();
+        let mut downlinks: Vec<(String, u8)> = Vec::new();
+        for config in configs.iter() {
+            if config.min_id > MAX_MIN_ID {
+                return Err(format!("invalid min_id: {}, expected 0 to {}", config.min_id, MAX_MIN_ID));
+            }
+            let decoder = match &config.decoder {
+                Some(name) => PayloadDecoder::new(name, config.struct_field.as_deref())?,
+                None if config.struct_field.is_some() => {
+                    return Err(format!("min_id {}: struct_field is only used by decoder struct", config.min_id));
+                }
+                None => config.kind.default_decoder(&decoder),
+            };
+            let message = MinMessage {
+                kind: config.kind,
+                decoder,
+                template: config.template.clone(),
+            };
+            if messages.insert(config.min_id, message).is_some() {
+                return Err(format!("duplicate min_id: {}", config.min_id));
+            }
+            if let Some(downlink) = &config.downlink {
+                if downlinks.iter().any(|(name, _)| name == downlink) {
+                    return Err(format!("duplicate downlink: {}", downlink));
+                }
+                downlinks.push((downlink.clone(), config.min_id));
+            }
+        }
+        Ok(MinRouter { messages, downlinks, decoder })
+    }
+
+    /// 处理收到的一帧，没有配置的 MIN ID 按 telemetry 处理
+    pub fn route(&self, min_id: u8, payload: &[u8]) -> Result<Routed, String> {
+        let (kind, decoder, template) = match self.messages.get(&min_id) {
+            Some(message) => (message.kind, &message.decoder, message.template.clone()),
+            None => (MessageKind::Telemetry, &self.decoder, None),
+        };
+        let decoded = decoder.decode(payload)
+            .map_err(|err| format!("decode MIN frame {} as {} failed: {}", min_id, kind.name(), err))?;
+        match kind {
+            MessageKind::Telemetry | MessageKind::Event | MessageKind::Binary => {
+                let mut data = OriginalData::new(&decoded.dump());
+                data.template = template;
+                data.calls.insert(String::from("MIN_ID"), min_id.into());
+                data.calls.insert(String::from("MIN_KIND"), kind.name().into());
+                Ok(Routed::Data(data))
+            }
+            MessageKind::Log | MessageKind::Ack => {
+                let message = match decoded["text"].as_str() {
+                    Some(text) if *decoder == PayloadDecoder::Text => text.to_string(),
+                    _ => decoded.dump(),
+                };
+                let mut fields = json::JsonValue::new_object();
+                fields["min_id"] = min_id.into();
+                if kind == MessageKind::Ack {
+                    fields["ack"] = decoded;
+                }
+                Ok(Routed::Log(LogEvent {
+                    event: format!("min_{}", kind.name()),
+                    message,
+                    fields,
+                }))
+            }
+        }
+    }
+
+    /// 下行消息使用的 MIN ID，主题中没有与 `downlink` 相同的一级时为 0
+    pub fn downlink_id(&self, topic: &str) -> u8 {
+        for level in topic.split('/') {
+            if let Some((_, min_id)) = self.downlinks.iter().find(|(name, _)| name == level) {
+                return *min_id;
+            }
+        }
+        DEFAULT_DOWNLINK_MIN_ID
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::min_message::*;
+
+    fn config(min_id: u8, kind: MessageKind, downlink: Option<&str>) -> MinMessageConfig {
+        MinMessageConfig {
+            min_id,
+            kind,
+            decoder: None,
+            struct_field: None,
+            template: None,
+            downlink: downlink.map(String::from),
+        }
+    }
+
+    #[test]
+    fn min_route() {
+        let mut telemetry = config(0x33, MessageKind::Telemetry, None);
+        telemetry.template = Some(String::from("{\"t\": <{t}>}"));
+        let configs = vec![
+            telemetry,
+            config(0x10, MessageKind::Binary, None),
+            config(0x11, MessageKind::Log, None),
+            config(0x12, MessageKind::Ack, None),
+        ];
+        let router = MinRouter::new(&configs, PayloadDecoder::Json).unwrap();
+
+        match router.route(0x33, b"{\"t\": 27.45}").unwrap() {
+            Routed::Data(data) => {
+                assert_eq!(json::parse(&data.msg).unwrap()["t"], 27.45);
+                assert_eq!(data.template, Some(String::from("{\"t\": <{t}>}")));
+                assert_eq!(data.calls["MIN_ID"], 0x33);
+                assert_eq!(data.calls["MIN_KIND"], "telemetry");
+            }
+            routed => panic!("unexpected {:?}", routed),
+        }
+        match router.route(0x10, &[0xde, 0xad]).unwrap() {
+            Routed::Data(data) => assert_eq!(json::parse(&data.msg).unwrap()["data"], "dead"),
+            routed => panic!("unexpected {:?}", routed),
+        }
+        match router.route(0x11, b"battery low").unwrap() {
+            Routed::Log(event) => {
+                assert_eq!(event.event, "min_log");
+                assert_eq!(event.message, "battery low");
+            }
+            routed => panic!("unexpected {:?}", routed),
+        }
+        match router.route(0x12, b"turn_on ok").unwrap() {
+            Routed::Log(event) => {
+                assert_eq!(event.event, "min_ack");
+                assert_eq!(event.fields["ack"]["text"], "turn_on ok");
+            }
+            routed => panic!("unexpected {:?}", routed),
+        }
+        // 没有配置的 MIN ID 按 telemetry 处理
+        match router.route(0x01, b"{\"h\": 25.36}").unwrap() {
+            Routed::Data(data) => assert_eq!(data.template, None),
+            routed => panic!("unexpected {:?}", routed),
+        }
+        assert!(router.route(0x33, b"not json").is_err());
+
+        // 数据接口配置的解码器用于没有配置的 MIN ID
+        let router = MinRouter::new(&[], PayloadDecoder::CayenneLpp).unwrap();
+        match router.route(0x33, &[0x01, 0x67, 0x01, 0x10]).unwrap() {
+            Routed::Data(data) => assert_eq!(data.msg, r#"{"temperature_1":27.2}"#),
+            routed => panic!("unexpected {:?}", routed),
+        }
+
+        // 每个 MIN ID 可以有自己的结构体布局
+        let toml_str = "min_id = 0x20\ndecoder = \"struct\"\n[[struct_field]]\nname = \"t\"\noffset = 0\ntype = \"i16\"\nscale = 0.5";
+        let router = MinRouter::new(&[toml::from_str(toml_str).unwrap()], PayloadDecoder::Json).unwrap();
+        match router.route(0x20, &[0x37, 0x00]).unwrap() {
+            Routed::Data(data) => assert_eq!(data.msg, r#"{"t":27.5}"#),
+            routed => panic!("unexpected {:?}", routed),
+        }
+        assert!(router.route(0x20, &[0x37]).is_err());
+    }
+
+    #[test]
+    fn min_downlink() {
+        let configs = vec![
+            config(0x20, MessageKind::Telemetry, Some("led")),
+            config(0x21, MessageKind::Telemetry, Some("relay")),
+        ];
+        let router = MinRouter::new(&configs, PayloadDecoder::Json).unwrap();
+        assert_eq!(router.downlink_id("ctrl/uart/led"), 0x20);
+        assert_eq!(router.downlink_id("ctrl/relay/1"), 0x21);
+        assert_eq!(router.downlink_id("ctrl/uart"), 0);
+
+        assert!(MinRouter::new(&[config(0x3F, MessageKind::Log, None)], PayloadDecoder::Json).is_ok());
+        assert!(MinRouter::new(&[config(0x40, MessageKind::Log, None)], PayloadDecoder::Json).is_err());
+        assert!(MinRouter::new(&[config(1, MessageKind::Log, None), config(1, MessageKind::Ack, None)], PayloadDecoder::Json).is_err());
+        assert!(MinRouter::new(&[config(1, MessageKind::Log, Some("a")), config(2, MessageKind::Ack, Some("a"))], PayloadDecoder::Json).is_err());
+        let config: MinMessageConfig = toml::from_str("min_id = 0x10\nkind = \"binary\"\ndownlink = \"fw\"").unwrap();
+        assert_eq!(config.min_id, 0x10);
+        assert_eq!(config.kind, MessageKind::Binary);
+
+        let bad_decoder = MinMessageConfig { decoder: Some(String::from("xml")), ..config };
+        assert!(MinRouter::new(&[bad_decoder], PayloadDecoder::Json).is_err());
+    }
+}
@@ -0,0 +1,1091 @@
+extern crate log;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::prelude::*;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use serde_derive::Deserialize;
+use serialport::{ClearBuffer, SerialPort};
+use crate::data_source::{DataIfError, DataSource, Health, LogEvent};
+use crate::interface::SerialSettings;
+use crate::types::{DataIfConfig, OriginalData};
+
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+// 一次最多读取的寄存器数量
+pub const MAX_READ_REGISTERS: u16 = 125;
+pub const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+pub const DEFAULT_RESPONSE_TIMEOUT_MS: u64 = 1000;
+
+#[derive(Debug)]
+pub enum ModbusError {
+    Io(io::Error),
+    Timeout,
+    Crc,
+    // 响应的从站地址、功能码或长度不对
+    InvalidResponse(String),
+    // 从站返回的异常码
+    Exception(u8),
+}
+
+impl From<io::Error> for ModbusError {
+    fn from(error: io::Error) -> Self {
+        ModbusError::Io(error)
+    }
+}
+
+impl fmt::Display for ModbusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModbusError::Io(err) => write!(f, "io error: {}", err),
+            ModbusError::Timeout => write!(f, "response timeout"),
+            ModbusError::Crc => write!(f, "crc error"),
+            ModbusError::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+            ModbusError::Exception(code) => write!(f, "exception code 0x{:02X}", code),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RegisterType {
+    #[default]
+    Holding,
+    Input,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+}
+
+impl DataType {
+    pub fn registers(&self) -> u16 {
+        match self {
+            DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Big,
+    Little,
+}
+
+/// 寄存器到 JSON 字段的映射
+#[derive(Deserialize, Debug, Clone)]
+pub struct FieldConfig {
+    pub name: String,
+    #[serde(default)]
+    pub register: RegisterType,
+    pub address: u16,
+    #[serde(rename = "type")]
+    pub data_type: DataType,
+    // 寄存器内的字节序
+    #[serde(default)]
+    pub byte_order: Order,
+    // 32 位数据的两个寄存器的顺序
+    #[serde(default)]
+    pub word_order: Order,
+    // 实际值 = 寄存器值 * scale
+    pub scale: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeviceConfig {
+    pub slave: u8,
+    // 设备名称，输出为 "device" 字段，默认为从站地址
+    pub name: Option<String>,
+    // 字段之间未映射的寄存器不超过该数量时合并成一次读取，默认 0，只合并地址连续的字段。
+    // 很多设备读取未映射的地址时返回异常码 0x02，确认设备支持后再设置
+    pub read_gap: Option<u16>,
+    pub field: Vec<FieldConfig>,
+}
+
+impl DeviceConfig {
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.slave.to_string(),
+        }
+    }
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// 一次读取的寄存器范围
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadBlock {
+    pub register: RegisterType,
+    pub address: u16,
+    pub count: u16,
+}
+
+/// 将字段按寄存器类型和地址合并成尽量少的读取范围，`max_gap` 为允许一并读取的未映射寄存器数量
+pub fn plan_reads(fields: &[FieldConfig], max_gap: u16) -> Vec<ReadBlock> {
+    let mut blocks: Vec<ReadBlock> = Vec::new();
+    for register in [RegisterType::Holding, RegisterType::Input].iter() {
+        // 结束地址（不含）可能是 0x10000，用 u32 计算
+        let mut ranges: Vec<(u32, u32)> = fields.iter()
+            .filter(|field| field.register == *register)
+            .map(|field| (field.address as u32, field.address as u32 + field.data_type.registers() as u32))
+            .collect();
+        ranges.sort();
+        let mut current: Option<ReadBlock> = None;
+        for (start, end) in ranges {
+            if let Some(block) = current.as_mut() {
+                let block_start = block.address as u32;
+                let block_end = block_start + block.count as u32;
+                if start <= block_end + max_gap as u32 && end.max(block_end) - block_start <= MAX_READ_REGISTERS as u32 {
+                    block.count = (end.max(block_end) - block_start) as u16;
+                    continue;
+                }
+                blocks.push(block.clone());
+            }
+            current = Some(ReadBlock {
+                register: *register,
+                address: start as u16,
+                count: (end - start) as u16,
+            });
+        }
+        if let Some(block) = current {
+            blocks.push(block);
+        }
+    }
+    blocks
+}
+
+fn word_bytes(word: u16, byte_order: Order) -> [u8; 2] {
+    match byte_order {
+        Order::Big => word.to_be_bytes(),
+        Order::Little => word.to_le_bytes(),
+    }
+}
+
+/// 将寄存器的值解码为 JSON 值，`regs` 从字段的第一个寄存器开始
+pub fn decode_field(regs: &[u16], field: &FieldConfig) -> Option<json::JsonValue> {
+    if regs.len() < field.data_type.registers() as usize {
+        return None;
+    }
+    let value: f64 = match field.data_type {
+        DataType::U16 => u16::from_be_bytes(word_bytes(regs[0], field.byte_order)) as f64,
+        DataType::I16 => i16::from_be_bytes(word_bytes(regs[0], field.byte_order)) as f64,
+        DataType::U32 | DataType::I32 | DataType::F32 => {
+            let (hi, lo) = match field.word_order {
+                Order::Big => (regs[0], regs[1]),
+                Order::Little => (regs[1], regs[0]),
+            };
+            let hi = word_bytes(hi, field.byte_order);
+            let lo = word_bytes(lo, field.byte_order);
+            let bytes = [hi[0], hi[1], lo[0], lo[1]];
+            match field.data_type {
+                DataType::U32 => u32::from_be_bytes(bytes) as f64,
+                DataType::I32 => i32::from_be_bytes(bytes) as f64,
+                _ => f32::from_be_bytes(bytes) as f64,
+            }
+        }
+    };
+    match field.scale {
+        Some(scale) => Some(json::JsonValue::from(value * scale)),
+        None => {
+            if field.data_type == DataType::F32 {
+                Some(json::JsonValue::from(value))
+            } else {
+                Some(json::JsonValue::from(value as i64))
+            }
+        }
+    }
+}
+
+/// 按字段的类型和缩放比例将值编码为寄存器，超出类型范围时返回错误
+pub fn encode_field(value: f64, field: &FieldConfig) -> Result<Vec<u16>, String> {
+    let raw = match field.scale {
+        Some(scale) if scale != 0.0 => value / scale,
+        _ => value,
+    };
+    let (min, max) = match field.data_type {
+        DataType::U16 => (u16::MIN as f64, u16::MAX as f64),
+        DataType::I16 => (i16::MIN as f64, i16::MAX as f64),
+        DataType::U32 => (u32::MIN as f64, u32::MAX as f64),
+        DataType::I32 => (i32::MIN as f64, i32::MAX as f64),
+        DataType::F32 => (f32::MIN as f64, f32::MAX as f64),
+    };
+    let rounded = if field.data_type == DataType::F32 { raw } else { raw.round() };
+    if !(min..=max).contains(&rounded) {
+        return Err(format!("value {} of field {} is out of range", value, field.name));
+    }
+    let from_bytes = |bytes: [u8; 2]| match field.byte_order {
+        Order::Big => u16::from_be_bytes(bytes),
+        Order::Little => u16::from_le_bytes(bytes),
+    };
+    let bytes: Vec<u8> = match field.data_type {
+        DataType::U16 => (raw.round() as u16).to_be_bytes().to_vec(),
+        DataType::I16 => (raw.round() as i16).to_be_bytes().to_vec(),
+        DataType::U32 => (raw.round() as u32).to_be_bytes().to_vec(),
+        DataType::I32 => (raw.round() as i32).to_be_bytes().to_vec(),
+        DataType::F32 => (raw as f32).to_be_bytes().to_vec(),
+    };
+    let mut words: Vec<u16> = bytes.chunks(2).map(|b| from_bytes([b[0], b[1]])).collect();
+    if field.word_order == Order::Little {
+        words.reverse();
+    }
+    Ok(words)
+}
+
+pub fn read_request(block: &ReadBlock) -> Vec<u8> {
+    let function = match block.register {
+        RegisterType::Holding => READ_HOLDING_REGISTERS,
+        RegisterType::Input => READ_INPUT_REGISTERS,
+    };
+    let mut pdu = vec![function];
+    pdu.extend_from_slice(&block.address.to_be_bytes());
+    pdu.extend_from_slice(&block.count.to_be_bytes());
+    pdu
+}
+
+pub fn write_request(address: u16, values: &[u16]) -> Vec<u8> {
+    let mut pdu = Vec::new();
+    if values.len() == 1 {
+        pdu.push(WRITE_SINGLE_REGISTER);
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&values[0].to_be_bytes());
+    } else {
+        pdu.push(WRITE_MULTIPLE_REGISTERS);
+        pdu.extend_from_slice(&address.to_be_bytes());
+        pdu.extend_from_slice(&(values.len() as u16).to_be_bytes());
+        pdu.push((values.len() * 2) as u8);
+        for value in values {
+            pdu.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    pdu
+}
+
+/// 检查响应 PDU 的功能码，返回异常码对应的错误
+pub fn check_response(request: &[u8], response: &[u8]) -> Result<(), ModbusError> {
+    if response.is_empty() {
+        return Err(ModbusError::InvalidResponse(String::from("empty pdu")));
+    }
+    if response[0] == request[0] | 0x80 {
+        return match response.get(1) {
+            Some(code) => Err(ModbusError::Exception(*code)),
+            None => Err(ModbusError::InvalidResponse(String::from("truncated exception"))),
+        };
+    }
+    if response[0] != request[0] {
+        return Err(ModbusError::InvalidResponse(format!("function code 0x{:02X}", response[0])));
+    }
+    Ok(())
+}
+
+/// 解析读寄存器的响应 PDU
+pub fn parse_read_response(request: &[u8], response: &[u8], count: u16) -> Result<Vec<u16>, ModbusError> {
+    check_response(request, response)?;
+    let byte_count = count as usize * 2;
+    if response.len() < 2 || response[1] as usize != byte_count || response.len() < 2 + byte_count {
+        return Err(ModbusError::InvalidResponse(format!("length {}", response.len())));
+    }
+    Ok(response[2..2 + byte_count].chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect())
+}
+
+/// 根据请求 PDU 计算正常响应 PDU 的长度
+pub fn response_len(request: &[u8]) -> usize {
+    match request[0] {
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => 2 + 2 * u16::from_be_bytes([request[3], request[4]]) as usize,
+        _ => 5,
+    }
+}
+
+/// 写寄存器的下行命令
+#[derive(Debug, PartialEq)]
+pub struct WriteCommand {
+    pub slave: u8,
+    pub address: u16,
+    pub values: Vec<u16>,
+}
+
+/// 解析下行消息。支持两种格式：
+///
+/// - `{"slave": 1, "address": 10, "values": [1, 2]}`：直接写寄存器
+/// - `{"slave": 1, "field": "setpoint", "value": 12.5}`：按字段的类型、字节序和缩放比例写入
+pub fn parse_command(msg: &str, devices: &[DeviceConfig]) -> Result<WriteCommand, String> {
+    let parsed = match json::parse(msg) {
+        Ok(parsed) => parsed,
+        Err(_) => return Err(format!("not a JSON object: {}", msg)),
+    };
+    let slave = match parsed["slave"].as_u8() {
+        Some(slave) => slave,
+        None => return Err(String::from("missing slave")),
+    };
+    if let Some(name) = parsed["field"].as_str() {
+        let field = devices.iter()
+            .filter(|device| device.slave == slave)
+            .flat_map(|device| device.field.iter())
+            .find(|field| field.name == name);
+        let field = match field {
+            Some(field) => field,
+            None => return Err(format!("unknown field {} of slave {}", name, slave)),
+        };
+        if field.register != RegisterType::Holding {
+            return Err(format!("field {} is not a holding register", name));
+        }
+        let value = match parsed["value"].as_f64() {
+            Some(value) => value,
+            None => return Err(String::from("missing value")),
+        };
+        return Ok(WriteCommand {
+            slave,
+            address: field.address,
+            values: encode_field(value, field)?,
+        });
+    }
+    let address = match parsed["address"].as_u16() {
+        Some(address) => address,
+        None => return Err(String::from("missing address or field")),
+    };
+    let mut values = Vec::new();
+    if parsed["values"].is_array() {
+        for value in parsed["values"].members() {
+            match value.as_u16() {
+                Some(value) => values.push(value),
+                None => return Err(format!("invalid register value: {}", value)),
+            }
+        }
+    } else if let Some(value) = parsed["value"].as_u16() {
+        values.push(value);
+    }
+    if values.is_empty() || values.len() > 123 {
+        return Err(String::from("values must have 1 to 123 registers"));
+    }
+    Ok(WriteCommand {
+        slave,
+        address,
+        values,
+    })
+}
+
+/// Modbus 传输层，发送请求 PDU，返回响应 PDU
+pub trait Transport {
+    fn transact(&mut self, slave: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError>;
+}
+
+/// Modbus RTU 主站
+pub struct RtuTransport {
+    port: Box<dyn SerialPort>,
+    // 帧间隔（3.5 个字符时间）
+    frame_delay: Duration,
+    response_timeout: Duration,
+}
+
+impl RtuTransport {
+    pub fn new(port: Box<dyn SerialPort>, baud_rate: u32, response_timeout: Duration) -> Self {
+        // 每个字符 11 位，波特率大于 19200 时固定为 1.75 ms
+        let frame_delay = if baud_rate > 19200 {
+            Duration::from_micros(1750)
+        } else {
+            Duration::from_micros(38_500_000 / baud_rate as u64)
+        };
+        RtuTransport {
+            port,
+            frame_delay,
+            response_timeout,
+        }
+    }
+}
+
+impl Transport for RtuTransport {
+    fn transact(&mut self, slave: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError> {
+        let mut frame = vec![slave];
+        frame.extend_from_slice(pdu);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+        // 丢弃之前残留的数据
+        let _ = self.port.clear(ClearBuffer::Input);
+        thread::sleep(self.frame_delay);
+        self.port.write_all(&frame)?;
+
+        let expected = response_len(pdu) + 3;
+        let mut response: Vec<u8> = Vec::new();
+        let mut buf = [0_u8; 256];
+        let deadline = Instant::now() + self.response_timeout;
+        loop {
+            // 异常响应固定为 5 字节
+            if response.len() >= expected || (response.len() >= 5 && response[1] & 0x80 != 0) {
+                break;
+            }
+            if Instant::now() > deadline {
+                return Err(ModbusError::Timeout);
+            }
+            match self.port.read(&mut buf) {
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(ModbusError::Io(err)),
+            }
+        }
+        let len = if response[1] & 0x80 != 0 { 5 } else { expected };
+        let response = &response[..len];
+        let crc = u16::from_le_bytes([response[len - 2], response[len - 1]]);
+        if crc16(&response[..len - 2]) != crc {
+            return Err(ModbusError::Crc);
+        }
+        if response[0] != slave {
+            return Err(ModbusError::InvalidResponse(format!("slave {}", response[0])));
+        }
+        Ok(response[1..len - 2].to_vec())
+    }
+}
+
+pub const DEFAULT_TCP_PORT: u16 = 502;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const MBAP_HEADER_LEN: usize = 7;
+
+/// Modbus TCP 客户端，连接断开后在下次请求时重连
+pub struct TcpTransport {
+    addr: String,
+    stream: Option<TcpStream>,
+    // 上次连接失败的时间，重连间隔 RECONNECT_INTERVAL
+    last_connect_failure: Option<Instant>,
+    transaction_id: u16,
+    response_timeout: Duration,
+}
+
+/// 没有端口时加上 502。不带方括号、有多个冒号的是 IPv6 地址，例如 `fe80::1`
+fn with_default_port(addr: &str) -> String {
+    if addr.starts_with('[') {
+        if addr.contains("]:") {
+            addr.to_string()
+        } else {
+            format!("{}:{}", addr, DEFAULT_TCP_PORT)
+        }
+    } else {
+        match addr.matches(':').count() {
+            0 => format!("{}:{}", addr, DEFAULT_TCP_PORT),
+            1 => addr.to_string(),
+            _ => format!("[{}]:{}", addr, DEFAULT_TCP_PORT),
+        }
+    }
+}
+
+impl TcpTransport {
+    pub fn new(addr: &str, response_timeout: Duration) -> Self {
+        TcpTransport {
+            addr: with_default_port(addr),
+            stream: None,
+            last_connect_failure: None,
+            transaction_id: 0,
+            response_timeout,
+        }
+    }
+
+    fn connect(&mut self) -> Result<&mut TcpStream, ModbusError> {
+        if self.stream.is_none() {
+            if let Some(failure) = self.last_connect_failure {
+                if failure.elapsed() < RECONNECT_INTERVAL {
+                    return Err(ModbusError::Io(io::Error::new(io::ErrorKind::NotConnected, "not connected")));
+                }
+            }
+            match self.open_stream() {
+                Ok(stream) => {
+                    info!("modbus tcp connected to {}", self.addr);
+                    self.last_connect_failure = None;
+                    self.stream = Some(stream);
+                }
+                Err(err) => {
+                    self.last_connect_failure = Some(Instant::now());
+                    return Err(ModbusError::Io(err));
+                }
+            }
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    fn open_stream(&self) -> io::Result<TcpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, format!("invalid address: {}", self.addr));
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.response_timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.response_timeout))?;
+                    stream.set_write_timeout(Some(self.response_timeout))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn exchange(&mut self, slave: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError> {
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let transaction_id = self.transaction_id;
+        let mut frame = Vec::with_capacity(MBAP_HEADER_LEN + pdu.len());
+        frame.extend_from_slice(&transaction_id.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(slave);
+        frame.extend_from_slice(pdu);
+
+        let deadline = Instant::now() + self.response_timeout;
+        let stream = self.connect()?;
+        stream.write_all(&frame)?;
+        loop {
+            let mut header = [0_u8; MBAP_HEADER_LEN];
+            stream.read_exact(&mut header)?;
+            let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if header[2..4] != [0, 0] || !(2..=254).contains(&len) {
+                return Err(ModbusError::InvalidResponse(format!("mbap header {:02X?}", header)));
+            }
+            let mut response = vec![0_u8; len - 1];
+            stream.read_exact(&mut response)?;
+            // 丢弃超时请求迟到的响应
+            if u16::from_be_bytes([header[0], header[1]]) != transaction_id {
+                if Instant::now() > deadline {
+                    return Err(ModbusError::Timeout);
+                }
+                continue;
+            }
+            if header[6] != slave {
+                return Err(ModbusError::InvalidResponse(format!("unit id {}", header[6])));
+            }
+            return Ok(response);
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn transact(&mut self, slave: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError> {
+        let result = self.exchange(slave, pdu);
+        // 读写出错后连接状态未知，断开后重连
+        if let Err(ModbusError::Io(err)) = &result {
+            if self.stream.take().is_some() {
+                warn!("modbus tcp connection to {} closed: {}", self.addr, err);
+            }
+        }
+        match result {
+            Err(ModbusError::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => {
+                Err(ModbusError::Timeout)
+            }
+            result => result,
+        }
+    }
+}
+
+/// 按配置的寄存器映射定时轮询从站，每个从站每次轮询输出一个 JSON 对象
+pub struct ModbusPoller {
+    devices: Vec<DeviceConfig>,
+    blocks: Vec<Vec<ReadBlock>>,
+    poll_interval: Duration,
+    last_poll: Option<Instant>,
+    // 每个从站最近一次轮询的错误
+    errors: HashMap<u8, String>,
+    events: Vec<LogEvent>,
+}
+
+impl ModbusPoller {
+    pub fn new(devices: Vec<DeviceConfig>, poll_interval: Duration) -> Self {
+        let blocks = devices.iter().map(|device| plan_reads(&device.field, device.read_gap.unwrap_or(0))).collect();
+        ModbusPoller {
+            devices,
+            blocks,
+            poll_interval,
+            last_poll: None,
+            errors: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &DataIfConfig) -> Result<Self, DataIfError> {
+        let devices = match &config.device {
+            Some(devices) if !devices.is_empty() => devices.clone(),
+            _ => {
+                let err = String::from("no modbus device configured, please add [[data_if.device]]");
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+        };
+        for device in devices.iter() {
+            if device.slave == 0 || device.slave > 247 {
+                let err = format!("invalid slave address: {}, expected 1 to 247", device.slave);
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+            // 字段占用的寄存器不能超出地址空间
+            for field in device.field.iter() {
+                if field.address as u32 + field.data_type.registers() as u32 > 0x10000 {
+                    let err = format!("field {} of slave {}: address {} is out of range", field.name, device.slave, field.address);
+                    error!("{}: {}", config.if_name, err);
+                    return Err(DataIfError::DataIfConfigError(err));
+                }
+            }
+        }
+        let poll_interval = Duration::from_millis(config.poll_interval_ms.unwrap_or(DEFAULT_POLL_INTERVAL_MS));
+        Ok(ModbusPoller::new(devices, poll_interval))
+    }
+
+    fn poll_device<T: Transport>(&self, transport: &mut T, index: usize) -> Result<json::JsonValue, ModbusError> {
+        let device = &self.devices[index];
+        let mut registers: HashMap<(RegisterType, u16), u16> = HashMap::new();
+        for block in self.blocks[index].iter() {
+            let request = read_request(block);
+            let response = transport.transact(device.slave, &request)?;
+            let values = parse_read_response(&request, &response, block.count)?;
+            for (i, value) in values.iter().enumerate() {
+                if let Some(address) = block.address.checked_add(i as u16) {
+                    registers.insert((block.register, address), *value);
+                }
+            }
+        }
+        let mut obj = json::JsonValue::new_object();
+        obj["device"] = device.name().into();
+        obj["slave"] = device.slave.into();
+        for field in device.field.iter() {
+            let regs: Vec<u16> = (0..field.data_type.registers())
+                .filter_map(|i| field.address.checked_add(i).and_then(|address| registers.get(&(field.register, address))).copied())
+                .collect();
+            if let Some(value) = decode_field(&regs, field) {
+                obj[field.name.as_str()] = value;
+            }
+        }
+        Ok(obj)
+    }
+
+    /// 到了轮询时间时轮询所有从站，返回轮询结果
+    pub fn poll<T: Transport>(&mut self, transport: &mut T) -> Vec<(u8, Result<json::JsonValue, ModbusError>)> {
+        if let Some(last_poll) = self.last_poll {
+            if last_poll.elapsed() < self.poll_interval {
+                return Vec::new();
+            }
+        }
+        self.last_poll = Some(Instant::now());
+        let mut results = Vec::new();
+        for i in 0..self.devices.len() {
+            let slave = self.devices[i].slave;
+            let result = self.poll_device(transport, i);
+            match &result {
+                Ok(_) => {
+                    if self.errors.remove(&slave).is_some() {
+                        info!("modbus slave {} recovered", slave);
+                        let event = self.log_event(i, "modbus_poll_recovered", format!("modbus slave {} recovered", slave));
+                        self.events.push(event);
+                    }
+                }
+                Err(err) => {
+                    let err = err.to_string();
+                    // 错误变化时才产生日志事件，避免每次轮询都发布
+                    if self.errors.get(&slave) != Some(&err) {
+                        warn!("poll modbus slave {} failed: {}", slave, err);
+                        let mut event = self.log_event(i, "modbus_poll_error", format!("poll modbus slave {} failed: {}", slave, err));
+                        event.fields["error"] = err.clone().into();
+                        self.events.push(event);
+                    } else {
+                        debug!("poll modbus slave {} failed: {}", slave, err);
+                    }
+                    self.errors.insert(slave, err);
+                }
+            }
+            results.push((slave, result));
+        }
+        results
+    }
+
+    fn log_event(&self, index: usize, event: &str, message: String) -> LogEvent {
+        let device = &self.devices[index];
+        let mut fields = json::JsonValue::new_object();
+        fields["device"] = device.name().into();
+        fields["slave"] = device.slave.into();
+        LogEvent {
+            event: event.to_string(),
+            message,
+            fields,
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<LogEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn write<T: Transport>(&self, transport: &mut T, msg: &str) -> Result<(), DataIfError> {
+        let command = match parse_command(msg, &self.devices) {
+            Ok(command) => command,
+            Err(err) => {
+                warn!("invalid modbus command: {}", err);
+                return Err(DataIfError::DataIfWriteError);
+            }
+        };
+        let request = write_request(command.address, &command.values);
+        match transport.transact(command.slave, &request).and_then(|response| check_response(&request, &response)) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("write modbus slave {} failed: {}", command.slave, err);
+                Err(DataIfError::DataIfWriteError)
+            }
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        if self.errors.is_empty() {
+            Health::Up
+        } else if self.errors.len() == self.devices.len() {
+            Health::Down(String::from("all slaves failed"))
+        } else {
+            let mut slaves: Vec<String> = self.errors.keys().map(|slave| slave.to_string()).collect();
+            slaves.sort();
+            Health::Degraded(format!("slave {} failed", slaves.join(", ")))
+        }
+    }
+}
+
+fn poll_results(results: Vec<(u8, Result<json::JsonValue, ModbusError>)>) -> Vec<OriginalData> {
+    results.into_iter()
+        .filter_map(|(_, result)| result.ok())
+        .map(|obj| OriginalData::new(&obj.dump()))
+        .collect()
+}
+
+/// 串口上的 Modbus RTU 主站数据接口
+pub struct ModbusRtuSource {
+    if_name: String,
+    settings: SerialSettings,
+    response_timeout: Duration,
+    poller: ModbusPoller,
+    transport: Option<RtuTransport>,
+}
+
+impl ModbusRtuSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let settings = match config.serial_settings() {
+            Ok(settings) => settings,
+            Err(err) => {
+                error!("{}: {}", config.if_name, err);
+                return Err(DataIfError::DataIfConfigError(err));
+            }
+        };
+        Ok(Box::new(ModbusRtuSource {
+            if_name: config.if_name.clone(),
+            settings,
+            response_timeout: Duration::from_millis(config.response_timeout_ms.unwrap_or(DEFAULT_RESPONSE_TIMEOUT_MS)),
+            poller: ModbusPoller::from_config(config)?,
+            transport: None,
+        }))
+    }
+}
+
+impl DataSource for ModbusRtuSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.settings.open(&self.if_name) {
+            Ok(port) => {
+                self.transport = Some(RtuTransport::new(port, self.settings.baud_rate, self.response_timeout));
+                Ok(())
+            }
+            Err(err) => {
+                error!("Open {} failed: {}", self.if_name, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let transport = match self.transport.as_mut() {
+            Some(transport) => transport,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        Ok(poll_results(self.poller.poll(transport)))
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        match self.transport.as_mut() {
+            Some(transport) => self.poller.write(transport, msg),
+            None => Err(DataIfError::DataIfWriteError),
+        }
+    }
+
+    fn health(&self) -> Health {
+        match self.transport {
+            Some(_) => self.poller.health(),
+            None => Health::Down(String::from("not opened")),
+        }
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        self.poller.take_events()
+    }
+}
+
+/// Modbus TCP 客户端数据接口，`if_name` 为服务器地址（默认端口 502）
+pub struct ModbusTcpSource {
+    poller: ModbusPoller,
+    transport: TcpTransport,
+}
+
+impl ModbusTcpSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let response_timeout = Duration::from_millis(config.response_timeout_ms.unwrap_or(DEFAULT_RESPONSE_TIMEOUT_MS));
+        Ok(Box::new(ModbusTcpSource {
+            poller: ModbusPoller::from_config(config)?,
+            transport: TcpTransport::new(&config.if_name, response_timeout),
+        }))
+    }
+}
+
+impl DataSource for ModbusTcpSource {
+    // 连接在轮询时建立，打开时设备不在线也不影响网关启动
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.transport.addr.to_socket_addrs() {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("Invalid modbus tcp address {}: {}", self.transport.addr, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        Ok(poll_results(self.poller.poll(&mut self.transport)))
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        self.poller.write(&mut self.transport, msg)
+    }
+
+    fn health(&self) -> Health {
+        self.poller.health()
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        self.poller.take_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::modbus::*;
+    use std::collections::HashMap;
+
+    fn field(name: &str, address: u16, data_type: DataType) -> FieldConfig {
+        FieldConfig {
+            name: name.to_string(),
+            register: RegisterType::Holding,
+            address,
+            data_type,
+            byte_order: Order::Big,
+            word_order: Order::Big,
+            scale: None,
+        }
+    }
+
+    // 模拟从站，保存寄存器的值
+    struct Slave {
+        slave: u8,
+        registers: HashMap<u16, u16>,
+    }
+
+    impl Transport for Slave {
+        fn transact(&mut self, slave: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError> {
+            if slave != self.slave {
+                return Err(ModbusError::Timeout);
+            }
+            let address = u16::from_be_bytes([pdu[1], pdu[2]]);
+            match pdu[0] {
+                READ_HOLDING_REGISTERS => {
+                    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+                    let mut response = vec![pdu[0], (count * 2) as u8];
+                    for i in 0..count {
+                        let value = self.registers.get(&(address + i)).copied().unwrap_or(0);
+                        response.extend_from_slice(&value.to_be_bytes());
+                    }
+                    Ok(response)
+                }
+                WRITE_MULTIPLE_REGISTERS => {
+                    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+                    for i in 0..count as usize {
+                        let value = u16::from_be_bytes([pdu[6 + i * 2], pdu[7 + i * 2]]);
+                        self.registers.insert(address + i as u16, value);
+                    }
+                    Ok(pdu[..5].to_vec())
+                }
+                _ => Ok(vec![pdu[0] | 0x80, 0x01]),
+            }
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).to_le_bytes(), [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn plan() {
+        let mut fields = vec![field("a", 0, DataType::U16), field("b", 4, DataType::F32), field("c", 200, DataType::U16)];
+        fields[2].register = RegisterType::Holding;
+        fields.push(FieldConfig { register: RegisterType::Input, ..field("d", 0, DataType::U16) });
+        assert_eq!(plan_reads(&fields, 8), vec![
+            ReadBlock { register: RegisterType::Holding, address: 0, count: 6 },
+            ReadBlock { register: RegisterType::Holding, address: 200, count: 1 },
+            ReadBlock { register: RegisterType::Input, address: 0, count: 1 },
+        ]);
+        // 默认不读取字段之间未映射的寄存器
+        assert_eq!(plan_reads(&fields, 0), vec![
+            ReadBlock { register: RegisterType::Holding, address: 0, count: 1 },
+            ReadBlock { register: RegisterType::Holding, address: 4, count: 2 },
+            ReadBlock { register: RegisterType::Holding, address: 200, count: 1 },
+            ReadBlock { register: RegisterType::Input, address: 0, count: 1 },
+        ]);
+
+        // 地址空间末尾的字段
+        let fields = vec![field("a", 65530, DataType::U16), field("b", 65534, DataType::U32)];
+        assert_eq!(plan_reads(&fields, 8), vec![ReadBlock { register: RegisterType::Holding, address: 65530, count: 6 }]);
+        let config = |address: u16| -> DataIfConfig {
+            toml::from_str(&format!("if_name = \"/dev/ttyUSB0\"\nif_type = \"modbus_rtu\"\n\
+                [[device]]\nslave = 1\n[[device.field]]\nname = \"f\"\naddress = {}\ntype = \"f32\"", address)).unwrap()
+        };
+        assert!(ModbusPoller::from_config(&config(65534)).is_ok());
+        assert!(ModbusPoller::from_config(&config(65535)).is_err());
+    }
+
+    #[test]
+    fn decode() {
+        let regs = [0x4148, 0x0000];
+        assert_eq!(decode_field(&regs, &field("f", 0, DataType::F32)).unwrap(), 12.5);
+        let f = FieldConfig { word_order: Order::Little, ..field("f", 0, DataType::F32) };
+        assert_eq!(decode_field(&[0x0000, 0x4148], &f).unwrap(), 12.5);
+        assert_eq!(decode_field(&[0xFFFE], &field("i", 0, DataType::I16)).unwrap(), -2);
+        let f = FieldConfig { byte_order: Order::Little, ..field("u", 0, DataType::U16) };
+        assert_eq!(decode_field(&[0x0100], &f).unwrap(), 1);
+        let f = FieldConfig { scale: Some(0.1), ..field("v", 0, DataType::U32) };
+        assert_eq!(decode_field(&[0x0000, 2295], &f).unwrap(), 229.5);
+        assert_eq!(encode_field(229.5, &f).unwrap(), vec![0x0000, 2295]);
+        assert!(encode_field(-0.1, &f).is_err());
+        assert!(encode_field(429496729.6, &f).is_err());
+        assert_eq!(encode_field(-32768.0, &field("i", 0, DataType::I16)).unwrap(), vec![0x8000]);
+        assert!(encode_field(32768.0, &field("i", 0, DataType::I16)).is_err());
+        assert!(encode_field(1e39, &field("f", 0, DataType::F32)).is_err());
+        assert!(decode_field(&[0x0000], &f).is_none());
+    }
+
+    #[test]
+    fn poll_and_write() {
+        let mut slave = Slave { slave: 1, registers: HashMap::new() };
+        slave.registers.insert(0, 2295);
+        slave.registers.insert(4, 0x4148);
+        slave.registers.insert(5, 0x0000);
+        let devices = vec![DeviceConfig {
+            slave: 1,
+            name: Some(String::from("meter-1")),
+            read_gap: None,
+            field: vec![
+                FieldConfig { scale: Some(0.1), ..field("voltage", 0, DataType::U16) },
+                field("setpoint", 4, DataType::F32),
+            ],
+        }];
+        let mut poller = ModbusPoller::new(devices, Duration::from_secs(60));
+        let results = poller.poll(&mut slave);
+        assert_eq!(results.len(), 1);
+        let obj = results[0].1.as_ref().unwrap();
+        assert_eq!(obj["device"], "meter-1");
+        assert_eq!(obj["voltage"], 229.5);
+        assert_eq!(obj["setpoint"], 12.5);
+        // 未到轮询时间
+        assert!(poller.poll(&mut slave).is_empty());
+
+        poller.write(&mut slave, "{\"slave\": 1, \"field\": \"setpoint\", \"value\": 20.0}").unwrap();
+        assert_eq!(slave.registers[&4], 0x41A0);
+        poller.write(&mut slave, "{\"slave\": 1, \"address\": 10, \"values\": [1, 2]}").unwrap();
+        assert_eq!(slave.registers[&11], 2);
+        assert!(poller.write(&mut slave, "{\"slave\": 1, \"field\": \"unknown\", \"value\": 1}").is_err());
+        assert_eq!(poller.health(), Health::Up);
+    }
+
+    #[test]
+    fn poll_error_events() {
+        let mut slave = Slave { slave: 2, registers: HashMap::new() };
+        let devices = vec![DeviceConfig {
+            slave: 1,
+            name: None,
+            read_gap: None,
+            field: vec![field("a", 0, DataType::U16)],
+        }];
+        let mut poller = ModbusPoller::new(devices, Duration::from_millis(0));
+        assert!(poller.poll(&mut slave)[0].1.is_err());
+        assert!(poller.poll(&mut slave)[0].1.is_err());
+        // 同样的错误只产生一次事件
+        let events = poller.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "modbus_poll_error");
+        assert_eq!(events[0].fields["slave"], 1);
+        assert_eq!(events[0].fields["error"], "response timeout");
+        assert_eq!(poller.health(), Health::Down(String::from("all slaves failed")));
+
+        slave.slave = 1;
+        assert!(poller.poll(&mut slave)[0].1.is_ok());
+        let events = poller.take_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "modbus_poll_recovered");
+        assert_eq!(poller.health(), Health::Up);
+    }
+
+    #[test]
+    fn tcp_transport() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0_u8; 12];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[2..], &[0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01]);
+            // 先回复一个过期的响应
+            stream.write_all(&[0xFF, 0xFF, 0x00, 0x00, 0x00, 0x05, 0x01, 0x03, 0x02, 0x00, 0x00]).unwrap();
+            stream.write_all(&[request[0], request[1], 0x00, 0x00, 0x00, 0x05, 0x01, 0x03, 0x02, 0x12, 0x34]).unwrap();
+        });
+        let mut transport = TcpTransport::new(&addr.to_string(), Duration::from_secs(1));
+        let block = ReadBlock { register: RegisterType::Holding, address: 0, count: 1 };
+        let request = read_request(&block);
+        let response = transport.transact(1, &request).unwrap();
+        assert_eq!(parse_read_response(&request, &response, 1).unwrap(), vec![0x1234]);
+        // 服务器断开后返回错误，下次请求时重连
+        assert!(transport.transact(1, &request).is_err());
+        assert!(transport.stream.is_none());
+
+        for (addr, expected) in [("192.168.1.10", "192.168.1.10:502"), ("plc.local:1502", "plc.local:1502"),
+            ("fe80::1", "[fe80::1]:502"), ("[fe80::1]", "[fe80::1]:502"), ("[::1]:1502", "[::1]:1502")].iter() {
+            assert_eq!(with_default_port(addr), *expected);
+        }
+    }
+
+    #[test]
+    fn exception() {
+        let request = read_request(&ReadBlock { register: RegisterType::Holding, address: 0, count: 1 });
+        match parse_read_response(&request, &[0x83, 0x02], 1) {
+            Err(ModbusError::Exception(0x02)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
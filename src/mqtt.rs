@@ -125,7 +125,7 @@ pub mod closure {
                     Err(_) => {},
                 }
                 // 发布其他线程发过来的 MQTT 消息
-                if let Ok(mqtt_message) = mqtt_message_receiver.try_recv() {
+                while let Ok(mqtt_message) = mqtt_message_receiver.try_recv() {
                     if let Err(e) = cli.publish(mqtt_message) {
                         error!("Error publishing message: {:?}", e);
                     }
@@ -149,16 +149,16 @@ pub mod closure {
         }
     }
 
-    pub fn sub_closure(rx: Receiver<MsgReceiver>, downstream_msg_tx: Sender<String>,
+    pub fn sub_closure(rx: Receiver<MsgReceiver>, downstream_msg_tx: Sender<paho_mqtt::Message>,
         cloud_statue_announcement_sender: Sender<Option<u8>>) -> impl FnOnce() -> () {
         move || loop {
             match rx.recv() {
                 Ok(r) => {
                     for msg in r.iter() {
                         if let Some(msg) = msg {
-                            if let Err(err) = downstream_msg_tx.send(String::from(msg.payload_str()))
+                            if let Err(err) = downstream_msg_tx.send(msg)
                             {
-                                error!("Send downstream msg failed(err: {}, msg: {})", err, msg);
+                                error!("Send downstream msg failed: {}", err);
                             }
                         } else {
                             if let Err(err) = cloud_statue_announcement_sender.send(None) {
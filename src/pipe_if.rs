@@ -0,0 +1,288 @@
+//! 标准输入和命名管道（FIFO）数据接口，用于从脚本或本机的其他程序接收数据。
+//!
+//! 两者都在后台线程中阻塞读取，按行（分隔符同 `serial_line`）切分后通过通道交给 `read`。
+//! 命名管道的写端全部关闭后重新打开，等待下一个写入者。
+
+extern crate log;
+
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use log::{debug, error, info, warn};
+use crate::data_source::{DataIfError, DataSource, Health};
+use crate::interface::{line_settings, LineBuffer};
+use crate::types::{DataIfConfig, OriginalData};
+
+// 读到 EOF 为止，最后一行没有分隔符时也作为一行。返回 Err 表示接收端已关闭
+fn read_lines<R: Read>(mut reader: R, lines: &mut LineBuffer, delimiter: u8, tx: &Sender<OriginalData>) -> io::Result<()> {
+    let mut buf = [0_u8; 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let data = if n == 0 { &[delimiter][..] } else { &buf[..n] };
+        for line in lines.push(data) {
+            if tx.send(OriginalData::new(&line)).is_err() {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "receiver closed"));
+            }
+        }
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+// 行分隔符和最大长度，配置方式同 serial_line
+fn line_config(config: &DataIfConfig) -> Result<(u8, usize), DataIfError> {
+    line_settings(&config.if_type, config.delimiter.as_deref(), config.max_line_len).map_err(|err| {
+        error!("{}: {}", config.if_name, err);
+        DataIfError::DataIfConfigError(err)
+    })
+}
+
+/// 标准输入，读到 EOF 后不再有数据
+pub struct StdinSource {
+    delimiter: u8,
+    max_line_len: usize,
+    rx: Option<Receiver<OriginalData>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl StdinSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let (delimiter, max_line_len) = line_config(config)?;
+        Ok(Box::new(StdinSource {
+            delimiter,
+            max_line_len,
+            rx: None,
+            closed: Arc::new(AtomicBool::new(false)),
+        }))
+    }
+}
+
+impl DataSource for StdinSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        let (tx, rx) = mpsc::channel();
+        let closed = self.closed.clone();
+        let (delimiter, max_line_len) = (self.delimiter, self.max_line_len);
+        let spawned = thread::Builder::new().name("stdin".into()).spawn(move || {
+            let mut lines = LineBuffer::new(delimiter, max_line_len);
+            match read_lines(io::stdin().lock(), &mut lines, delimiter, &tx) {
+                Ok(_) => warn!("stdin closed"),
+                Err(err) => debug!("read stdin failed: {}", err),
+            }
+            closed.store(true, Ordering::Relaxed);
+        });
+        match spawned {
+            Ok(_) => {
+                self.rx = Some(rx);
+                Ok(())
+            }
+            Err(err) => {
+                error!("Start stdin reader failed: {}", err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        match &self.rx {
+            Some(rx) => Ok(rx.try_iter().collect()),
+            None => Err(DataIfError::DataIfReadError),
+        }
+    }
+
+    // 标准输出用于控制台日志，不发送下行消息
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        debug!("stdin is read only, drop msg: {}", msg);
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        if self.closed.load(Ordering::Relaxed) {
+            Health::Down(String::from("stdin closed"))
+        } else {
+            Health::Up
+        }
+    }
+}
+
+/// 命名管道，不存在时创建
+pub struct FifoSource {
+    path: String,
+    delimiter: u8,
+    max_line_len: usize,
+    rx: Option<Receiver<OriginalData>>,
+    // 是否有写入者
+    connected: Arc<AtomicBool>,
+}
+
+impl FifoSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let (delimiter, max_line_len) = line_config(config)?;
+        Ok(Box::new(FifoSource {
+            path: config.if_name.clone(),
+            delimiter,
+            max_line_len,
+            rx: None,
+            connected: Arc::new(AtomicBool::new(false)),
+        }))
+    }
+
+    fn create_fifo(path: &str) -> io::Result<()> {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+            Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "not a named pipe")),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                let c_path = CString::new(Path::new(path).as_os_str().as_bytes())
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                if unsafe { libc::mkfifo(c_path.as_ptr(), 0o660) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                info!("created named pipe {}", path);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl DataSource for FifoSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        if let Err(err) = FifoSource::create_fifo(&self.path) {
+            error!("Open {} failed: {}", self.path, err);
+            return Err(DataIfError::DataIfOpenError);
+        }
+        let (tx, rx) = mpsc::channel();
+        let connected = self.connected.clone();
+        let path = self.path.clone();
+        let (delimiter, max_line_len) = (self.delimiter, self.max_line_len);
+        // 打开命名管道会阻塞到有写入者为止，写入者全部关闭后读到 EOF，重新打开等待下一个写入者
+        let spawned = thread::Builder::new().name("fifo".into()).spawn(move || loop {
+            let fifo = match File::open(&path) {
+                Ok(fifo) => fifo,
+                Err(err) => {
+                    error!("open {} failed: {}", path, err);
+                    return;
+                }
+            };
+            connected.store(true, Ordering::Relaxed);
+            let mut lines = LineBuffer::new(delimiter, max_line_len);
+            let result = read_lines(fifo, &mut lines, delimiter, &tx);
+            connected.store(false, Ordering::Relaxed);
+            match result {
+                Ok(_) => debug!("writer of {} closed, reopen", path),
+                Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return,
+                Err(err) => {
+                    error!("read {} failed: {}", path, err);
+                    return;
+                }
+            }
+        });
+        match spawned {
+            Ok(_) => {
+                self.rx = Some(rx);
+                Ok(())
+            }
+            Err(err) => {
+                error!("Start fifo reader for {} failed: {}", self.path, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        match &self.rx {
+            Some(rx) => Ok(rx.try_iter().collect()),
+            None => Err(DataIfError::DataIfReadError),
+        }
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        debug!("{} is read only, drop msg: {}", self.path, msg);
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        if self.rx.is_none() {
+            Health::Down(String::from("not opened"))
+        } else if self.connected.load(Ordering::Relaxed) {
+            Health::Up
+        } else {
+            Health::Degraded(String::from("waiting for writer"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::prelude::*;
+    use std::time::{Duration, Instant};
+    use crate::data_source::{DataSource, Health};
+    use crate::pipe_if::*;
+
+    fn config(if_name: &str, if_type: &str) -> DataIfConfig {
+        toml::from_str(&format!("if_name = \"{}\"\nif_type = \"{}\"", if_name, if_type)).unwrap()
+    }
+
+    fn read_until(source: &mut Box<dyn DataSource>, count: usize) -> Vec<String> {
+        let start = Instant::now();
+        let mut msgs = Vec::new();
+        while msgs.len() < count && start.elapsed() < Duration::from_secs(2) {
+            msgs.extend(source.read().unwrap().into_iter().map(|data| data.msg));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        msgs
+    }
+
+    #[test]
+    fn pipe_read_lines() {
+        let (tx, rx) = mpsc::channel();
+        let mut lines = LineBuffer::new(b'\n', 16);
+        read_lines(&b"{\"t\": 1}\r\n\n{\"t\": 2}"[..], &mut lines, b'\n', &tx).unwrap();
+        let msgs: Vec<String> = rx.try_iter().map(|data| data.msg).collect();
+        assert_eq!(msgs, vec!["{\"t\": 1}", "{\"t\": 2}"]);
+
+        let mut bad = config("-", "stdin");
+        bad.delimiter = Some(String::from("none"));
+        assert!(StdinSource::build(&bad).is_err());
+    }
+
+    #[test]
+    fn fifo_reopen() {
+        let path = std::env::temp_dir().join(format!("gw_fifo_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let mut source = FifoSource::build(&config(path, "fifo")).unwrap();
+        source.open().unwrap();
+        assert!(fs::metadata(path).unwrap().file_type().is_fifo());
+        assert_eq!(source.health(), Health::Degraded(String::from("waiting for writer")));
+
+        // 写入者关闭后重新打开，下一个写入者的数据仍能收到
+        for (i, msg) in ["{\"n\": 1}\n{\"n\": 2}\n", "{\"n\": 3}"].iter().enumerate() {
+            let mut writer = OpenOptions::new().write(true).open(path).unwrap();
+            writer.write_all(msg.as_bytes()).unwrap();
+            drop(writer);
+            let msgs = read_until(&mut source, 2 - i);
+            assert_eq!(msgs.len(), 2 - i);
+        }
+        let _ = fs::remove_file(path);
+
+        let file = std::env::temp_dir().join(format!("gw_not_fifo_{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let mut source = FifoSource::build(&config(file.to_str().unwrap(), "fifo")).unwrap();
+        assert!(source.open().is_err());
+        let _ = fs::remove_file(&file);
+    }
+}
@@ -0,0 +1,502 @@
+extern crate log;
+
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use crate::data_source::{DataIfError, DataSource, Health, LogEvent};
+use crate::decoder::{DecodeErrors, PayloadDecoder};
+use crate::interface::LineBuffer;
+use crate::types::{DataIfConfig, OriginalData};
+
+// 单行消息的最大长度，超过后丢弃该行
+pub const MAX_LINE_LEN: usize = 4096;
+pub const MAX_DATAGRAM_LEN: usize = 65507;
+pub const DEFAULT_MAX_CONNECTIONS: usize = 64;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+// 下行消息的发送超时，对端不读取数据时不会一直阻塞数据接口线程
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn read_lines<R: Read>(mut stream: R, peer: &str, tx: &Sender<OriginalData>) -> io::Result<()> {
+    let mut lines = LineBuffer::new(b'\n', MAX_LINE_LEN);
+    let mut buf = [0_u8; 1024];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        for line in lines.push(&buf[..n]) {
+            let data = OriginalData {
+                peer: Some(peer.to_string()),
+                ..OriginalData::new(&line)
+            };
+            if tx.send(data).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// 发送一条下行消息，总时间不超过 WRITE_TIMEOUT。write_all 每次部分写入后都会重新等待超时，对端不读取时阻塞时间没有上限
+fn write_line(stream: &mut TcpStream, msg: &str) -> io::Result<()> {
+    let line = format!("{}\n", msg);
+    let mut buf = line.as_bytes();
+    let deadline = Instant::now() + WRITE_TIMEOUT;
+    while !buf.is_empty() {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"));
+        }
+        stream.set_write_timeout(Some(deadline - now))?;
+        match stream.write(buf) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole line")),
+            Ok(n) => buf = &buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// TCP 服务端：每个连接一个线程，按行接收数据，下行消息发送给所有连接。
+pub struct TcpServerIf {
+    addr: String,
+    max_connections: usize,
+    local_addr: Option<SocketAddr>,
+    rx: Option<Receiver<OriginalData>>,
+    connections: Arc<Mutex<HashMap<SocketAddr, TcpStream>>>,
+}
+
+impl TcpServerIf {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let max_connections = config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        Ok(Box::new(TcpServerIf::new(&config.if_name, max_connections)))
+    }
+
+    pub fn new(addr: &str, max_connections: usize) -> Self {
+        TcpServerIf {
+            addr: addr.to_string(),
+            max_connections,
+            local_addr: None,
+            rx: None,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn listen(&mut self) -> io::Result<()> {
+        let listener = TcpListener::bind(&self.addr)?;
+        let local_addr = listener.local_addr()?;
+        info!("tcp server listening on {}", local_addr);
+        let (tx, rx) = mpsc::channel();
+        let accept_connections = self.connections.clone();
+        let max_connections = self.max_connections;
+        thread::Builder::new().name("tcp_server_accept".into()).spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(_) => continue,
+                };
+                let writer = match stream.try_clone() {
+                    Ok(writer) => writer,
+                    Err(_) => continue,
+                };
+                if let Err(e) = writer.set_write_timeout(Some(WRITE_TIMEOUT)) {
+                    debug!("set write timeout for {} failed: {}", peer, e);
+                    continue;
+                }
+                {
+                    let mut connections = accept_connections.lock().unwrap();
+                    if connections.len() >= max_connections {
+                        warn!("too many connections, reject {}", peer);
+                        continue;
+                    }
+                    connections.insert(peer, writer);
+                }
+                debug!("{} connected", peer);
+                let tx = tx.clone();
+                let connections = accept_connections.clone();
+                let spawned = thread::Builder::new().name(format!("tcp_conn_{}", peer)).spawn(move || {
+                    if let Err(e) = read_lines(stream, &peer.to_string(), &tx) {
+                        debug!("read from {} failed: {}", peer, e);
+                    }
+                    connections.lock().unwrap().remove(&peer);
+                    debug!("{} disconnected", peer);
+                });
+                if spawned.is_err() {
+                    accept_connections.lock().unwrap().remove(&peer);
+                }
+            }
+        })?;
+        self.local_addr = Some(local_addr);
+        self.rx = Some(rx);
+        Ok(())
+    }
+}
+
+impl DataSource for TcpServerIf {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.listen() {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("Listen on {} failed: {}", self.addr, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        match &self.rx {
+            Some(rx) => Ok(rx.try_iter().collect()),
+            None => Err(DataIfError::DataIfReadError),
+        }
+    }
+
+    // 发送失败或超时的连接被关闭，其读取线程随之退出。
+    // 发送时不持有连接表的锁，慢的连接不会阻塞接受新连接和读取线程
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let streams: Vec<(SocketAddr, TcpStream)> = self.connections.lock().unwrap().iter()
+            .filter_map(|(peer, stream)| stream.try_clone().ok().map(|stream| (*peer, stream)))
+            .collect();
+        let mut failed = Vec::new();
+        for (peer, mut stream) in streams {
+            if let Err(e) = write_line(&mut stream, msg) {
+                warn!("write to {} failed, close the connection: {}", peer, e);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                failed.push(peer);
+            }
+        }
+        if !failed.is_empty() {
+            let mut connections = self.connections.lock().unwrap();
+            for peer in failed.iter() {
+                connections.remove(peer);
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match self.local_addr {
+            Some(_) => {
+                if self.connections.lock().unwrap().len() >= self.max_connections {
+                    Health::Degraded(format!("{} connections, new connections are rejected", self.max_connections))
+                } else {
+                    Health::Up
+                }
+            }
+            None => Health::Down(String::from("not listening")),
+        }
+    }
+}
+
+/// TCP 客户端：连接到设备（或串口服务器），断开后自动重连。
+pub struct TcpClientIf {
+    addr: String,
+    rx: Option<Receiver<OriginalData>>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl TcpClientIf {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        Ok(Box::new(TcpClientIf::new(&config.if_name)))
+    }
+
+    pub fn new(addr: &str) -> Self {
+        TcpClientIf {
+            addr: addr.to_string(),
+            rx: None,
+            stream: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let writer = self.stream.clone();
+        let addr = self.addr.clone();
+        thread::Builder::new().name("tcp_client".into()).spawn(move || loop {
+            match TcpStream::connect(&addr) {
+                Ok(stream) => {
+                    info!("connected to {}", addr);
+                    if let Ok(clone) = stream.try_clone() {
+                        if let Err(e) = clone.set_write_timeout(Some(WRITE_TIMEOUT)) {
+                            debug!("set write timeout for {} failed: {}", addr, e);
+                        }
+                        *writer.lock().unwrap() = Some(clone);
+                    }
+                    if let Err(e) = read_lines(stream, &addr, &tx) {
+                        debug!("read from {} failed: {}", addr, e);
+                    }
+                    *writer.lock().unwrap() = None;
+                    warn!("connection to {} closed", addr);
+                }
+                Err(e) => debug!("connect to {} failed: {}", addr, e),
+            }
+            thread::sleep(RECONNECT_INTERVAL);
+        })?;
+        self.rx = Some(rx);
+        Ok(())
+    }
+}
+
+impl DataSource for TcpClientIf {
+    // 连接在后台线程中建立，打开时设备不在线也不影响网关启动
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.connect() {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("Start tcp client for {} failed: {}", self.addr, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        match &self.rx {
+            Some(rx) => Ok(rx.try_iter().collect()),
+            None => Err(DataIfError::DataIfReadError),
+        }
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let mut stream = self.stream.lock().unwrap();
+        if let Some(s) = stream.as_mut() {
+            if let Err(e) = write_line(s, msg) {
+                debug!("write failed: {}", e);
+                let _ = s.shutdown(std::net::Shutdown::Both);
+                *stream = None;
+                return Err(DataIfError::DataIfWriteError);
+            }
+            Ok(())
+        } else {
+            warn!("not connected, drop msg: {}", msg);
+            Err(DataIfError::DataIfWriteError)
+        }
+    }
+
+    fn health(&self) -> Health {
+        match *self.stream.lock().unwrap() {
+            Some(_) => Health::Up,
+            None => Health::Down(format!("not connected to {}", self.addr)),
+        }
+    }
+}
+
+// 对端超过该时间没有发送数据时不再向其发送下行消息
+const UDP_PEER_TIMEOUT: Duration = Duration::from_secs(600);
+// 每次读取最多处理的数据报数量，其余的留在接收缓冲区下次读取，避免大量数据报时一直不返回
+const MAX_DATAGRAMS_PER_READ: usize = 64;
+
+/// UDP：每个数据报是一条消息，下行消息发送给最近有数据上来的对端。
+pub struct UdpIf {
+    addr: String,
+    socket: Option<UdpSocket>,
+    peers: HashMap<SocketAddr, Instant>,
+    // 接收缓冲区，每次读取时复用
+    buf: Vec<u8>,
+    // 没有配置解码器时数据报按 UTF-8 文本处理
+    decoder: Option<PayloadDecoder>,
+    decode_errors: DecodeErrors,
+}
+
+impl UdpIf {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let mut udp = UdpIf::new(&config.if_name);
+        udp.decoder = PayloadDecoder::from_config(config)?;
+        Ok(Box::new(udp))
+    }
+
+    pub fn new(addr: &str) -> Self {
+        UdpIf {
+            addr: addr.to_string(),
+            socket: None,
+            peers: HashMap::new(),
+            buf: vec![0_u8; MAX_DATAGRAM_LEN],
+            decoder: None,
+            decode_errors: DecodeErrors::new(addr),
+        }
+    }
+
+    fn bind(&mut self) -> io::Result<()> {
+        let socket = UdpSocket::bind(&self.addr)?;
+        socket.set_nonblocking(true)?;
+        info!("udp listening on {}", socket.local_addr()?);
+        self.socket = Some(socket);
+        Ok(())
+    }
+}
+
+impl DataSource for UdpIf {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        match self.bind() {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                error!("Bind {} failed: {}", self.addr, err);
+                Err(DataIfError::DataIfOpenError)
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return Err(DataIfError::DataIfReadError),
+        };
+        let mut msgs = Vec::new();
+        let buf = &mut self.buf;
+        for _ in 0..MAX_DATAGRAMS_PER_READ {
+            match socket.recv_from(buf) {
+                Ok((n, peer)) => {
+                    self.peers.insert(peer, Instant::now());
+                    let msg = match &self.decoder {
+                        Some(decoder) => match self.decode_errors.decode(decoder, &buf[..n]) {
+                            Some(msg) => msg,
+                            None => continue,
+                        },
+                        None => String::from_utf8_lossy(&buf[..n]).trim().to_string(),
+                    };
+                    if !msg.is_empty() {
+                        msgs.push(OriginalData {
+                            peer: Some(peer.to_string()),
+                            ..OriginalData::new(&msg)
+                        });
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    debug!("recv failed: {}", e);
+                    break;
+                }
+            }
+        }
+        Ok(msgs)
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return Err(DataIfError::DataIfWriteError),
+        };
+        self.peers.retain(|_, last| last.elapsed() < UDP_PEER_TIMEOUT);
+        for peer in self.peers.keys() {
+            if let Err(e) = socket.send_to(msg.as_bytes(), peer) {
+                debug!("send to {} failed: {}", peer, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        match self.socket {
+            Some(_) => Health::Up,
+            None => Health::Down(String::from("not bound")),
+        }
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        self.decode_errors.take_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_source::DataSource;
+    use crate::socket_if::{TcpServerIf, UdpIf, MAX_DATAGRAMS_PER_READ};
+    use std::io::prelude::*;
+    use std::net::{TcpStream, UdpSocket};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn tcp_server_lines() {
+        let mut server = TcpServerIf::new("127.0.0.1:0", 4);
+        server.open().unwrap();
+        let mut clients = Vec::new();
+        for i in 0..3 {
+            let mut client = TcpStream::connect(server.local_addr.unwrap()).unwrap();
+            client.write_all(format!("{{\"id\": {}", i).as_bytes()).unwrap();
+            clients.push(client);
+        }
+        for client in clients.iter_mut() {
+            client.write_all(b"}\n").unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        let msgs = server.read().unwrap();
+        assert_eq!(msgs.len(), 3);
+        for client in clients.iter() {
+            let peer = client.local_addr().unwrap().to_string();
+            assert!(msgs.iter().any(|m| m.peer.as_ref() == Some(&peer)));
+        }
+        server.write("turn_on").unwrap();
+        let mut buf = [0_u8; 8];
+        clients[0].set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        clients[0].read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"turn_on\n");
+    }
+
+    #[test]
+    fn tcp_server_slow_client() {
+        let mut server = TcpServerIf::new("127.0.0.1:0", 4);
+        server.open().unwrap();
+        // 不读取数据的连接填满发送缓冲区后超时并被关闭，不会一直阻塞
+        let _slow = TcpStream::connect(server.local_addr.unwrap()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let connections = server.connections.clone();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        let writer = std::thread::spawn(move || {
+            let _done = done_tx;
+            let msg = "x".repeat(1 << 20);
+            for _ in 0..256 {
+                server.write(&msg).unwrap();
+                if server.connections.lock().unwrap().is_empty() {
+                    break;
+                }
+            }
+        });
+        // 发送阻塞期间连接表的锁仍然可以很快拿到
+        while let Err(std::sync::mpsc::TryRecvError::Empty) = done_rx.try_recv() {
+            let start = Instant::now();
+            drop(connections.lock().unwrap());
+            assert!(start.elapsed() < Duration::from_millis(500));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        writer.join().unwrap();
+        assert!(connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn udp_datagram() {
+        let mut udp = UdpIf::new("127.0.0.1:0");
+        udp.open().unwrap();
+        let addr = udp.socket.as_ref().unwrap().local_addr().unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"{\"t\": 1}", addr).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let msgs = udp.read().unwrap();
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].msg, "{\"t\": 1}");
+        assert_eq!(msgs[0].peer, Some(client.local_addr().unwrap().to_string()));
+        udp.write("turn_on").unwrap();
+        let mut buf = [0_u8; 16];
+        client.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let (n, _) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"turn_on");
+
+        // 每次读取的数据报数量有上限，剩下的下次读取
+        for i in 0..MAX_DATAGRAMS_PER_READ + 6 {
+            client.send_to(format!("{{\"n\": {}}}", i).as_bytes(), addr).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(udp.read().unwrap().len(), MAX_DATAGRAMS_PER_READ);
+        assert_eq!(udp.read().unwrap().len(), 6);
+    }
+}
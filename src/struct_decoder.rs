@@ -0,0 +1,332 @@
+//! 按配置的字段布局解码 C 结构体形式的二进制帧（按字节紧凑排列，`#pragma pack(1)`），
+//! 对应 `[[data_if.struct_field]]` 和 `[[data_if.min_message.struct_field]]`。
+//!
+//! 每个字段输出为一个 JSON 属性：整数可以取其中的位域、映射为枚举名称，数值可以乘以 `scale`。
+
+use std::collections::HashMap;
+use serde_derive::Deserialize;
+use crate::decoder::{float, to_hex};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    // 一个字节，非 0 为 true
+    Bool,
+    // 输出为十六进制字符串，长度由 len 指定
+    Bytes,
+    // UTF-8 字符串，长度由 len 指定，遇到 0 结束
+    String,
+}
+
+impl FieldType {
+    fn size(&self) -> Option<usize> {
+        match self {
+            FieldType::U8 | FieldType::I8 | FieldType::Bool => Some(1),
+            FieldType::U16 | FieldType::I16 => Some(2),
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => Some(4),
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => Some(8),
+            FieldType::Bytes | FieldType::String => None,
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, FieldType::U8 | FieldType::I8 | FieldType::U16 | FieldType::I16
+            | FieldType::U32 | FieldType::I32 | FieldType::U64 | FieldType::I64)
+    }
+
+    fn is_signed(&self) -> bool {
+        matches!(self, FieldType::I8 | FieldType::I16 | FieldType::I32 | FieldType::I64)
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    // 大多数 MCU（ARM Cortex-M、AVR、ESP32）为小端
+    #[default]
+    Little,
+    Big,
+}
+
+/// 结构体字段定义
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct StructFieldConfig {
+    pub name: String,
+    // 字段在帧中的字节偏移
+    pub offset: usize,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub endian: Endian,
+    // bytes、string 的字节数
+    pub len: Option<usize>,
+    // 位域：整数或 bool 的第 bit_offset 位（最低位为 0）开始的 bit_len 位，bit_len 默认 1
+    pub bit_offset: Option<u32>,
+    pub bit_len: Option<u32>,
+    // 实际值 = 原始值 * scale
+    pub scale: Option<f64>,
+    // 整数值到名称的映射，例如 { 0 = "idle", 1 = "running" }，没有映射的值输出原始值
+    #[serde(rename = "enum")]
+    pub enum_names: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct StructField {
+    name: String,
+    offset: usize,
+    field_type: FieldType,
+    endian: Endian,
+    size: usize,
+    // (位偏移, 位数)
+    bits: Option<(u32, u32)>,
+    scale: Option<f64>,
+    enum_names: HashMap<i64, String>,
+}
+
+/// 检查过的结构体布局
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLayout {
+    fields: Vec<StructField>,
+    // 帧的最小长度
+    min_len: usize,
+}
+
+impl StructLayout {
+    pub fn new(configs: &[StructFieldConfig]) -> Result<Self, String> {
+        if configs.is_empty() {
+            return Err(String::from("decoder struct requires at least one struct_field"));
+        }
+        let mut fields: Vec<StructField> = Vec::with_capacity(configs.len());
+        for config in configs.iter() {
+            if fields.iter().any(|field| field.name == config.name) {
+                return Err(format!("duplicate struct field: {}", config.name));
+            }
+            let size = match (config.field_type.size(), config.len) {
+                (Some(size), None) => size,
+                (None, Some(len)) if len > 0 => len,
+                (None, _) => return Err(format!("struct field {}: len is required by bytes and string", config.name)),
+                (Some(_), Some(_)) => return Err(format!("struct field {}: len is only used by bytes and string", config.name)),
+            };
+            let bits = match (config.bit_offset, config.bit_len) {
+                (None, None) => None,
+                (bit_offset, bit_len) => {
+                    let (bit_offset, bit_len) = (bit_offset.unwrap_or(0), bit_len.unwrap_or(1));
+                    if !config.field_type.is_integer() && config.field_type != FieldType::Bool {
+                        return Err(format!("struct field {}: bit field requires an integer or bool", config.name));
+                    }
+                    if bit_len == 0 || bit_offset + bit_len > size as u32 * 8 {
+                        return Err(format!("struct field {}: bit field is out of {} bits", config.name, size * 8));
+                    }
+                    Some((bit_offset, bit_len))
+                }
+            };
+            if config.scale.is_some() && !config.field_type.is_integer()
+                && !matches!(config.field_type, FieldType::F32 | FieldType::F64) {
+                return Err(format!("struct field {}: scale requires a number", config.name));
+            }
+            let mut enum_names = HashMap::new();
+            if let Some(names) = &config.enum_names {
+                if !config.field_type.is_integer() {
+                    return Err(format!("struct field {}: enum requires an integer", config.name));
+                }
+                for (value, name) in names.iter() {
+                    let value = parse_integer(value)
+                        .ok_or_else(|| format!("struct field {}: invalid enum value: {}", config.name, value))?;
+                    enum_names.insert(value, name.clone());
+                }
+            }
+            fields.push(StructField {
+                name: config.name.clone(),
+                offset: config.offset,
+                field_type: config.field_type,
+                endian: config.endian,
+                size,
+                bits,
+                scale: config.scale,
+                enum_names,
+            });
+        }
+        let min_len = fields.iter().map(|field| field.offset + field.size).max().unwrap_or(0);
+        Ok(StructLayout { fields, min_len })
+    }
+
+    /// 解码一帧，帧比布局短时返回错误，多出的字节忽略
+    pub fn decode(&self, payload: &[u8]) -> Result<json::JsonValue, String> {
+        if payload.len() < self.min_len {
+            return Err(format!("frame too short: {} bytes, expected at least {}", payload.len(), self.min_len));
+        }
+        let mut obj = json::JsonValue::new_object();
+        for field in self.fields.iter() {
+            let bytes = &payload[field.offset..field.offset + field.size];
+            obj[field.name.as_str()] = field.decode(bytes)?;
+        }
+        Ok(obj)
+    }
+}
+
+impl StructField {
+    fn decode(&self, bytes: &[u8]) -> Result<json::JsonValue, String> {
+        match self.field_type {
+            FieldType::Bytes => return Ok(to_hex(bytes).into()),
+            FieldType::String => {
+                let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+                return std::str::from_utf8(&bytes[..end])
+                    .map(|text| text.into())
+                    .map_err(|err| format!("struct field {}: {}", self.name, err));
+            }
+            _ => {}
+        }
+        let mut raw = match self.endian {
+            Endian::Little => bytes.iter().rev().fold(0_u64, |value, byte| (value << 8) | *byte as u64),
+            Endian::Big => bytes.iter().fold(0_u64, |value, byte| (value << 8) | *byte as u64),
+        };
+        let mut bit_len = self.size as u32 * 8;
+        if let Some((bit_offset, len)) = self.bits {
+            raw = (raw >> bit_offset) & (u64::MAX >> (64 - len));
+            bit_len = len;
+        }
+        let value: f64 = match self.field_type {
+            FieldType::Bool => return Ok((raw != 0).into()),
+            FieldType::F32 => f32::from_bits(raw as u32) as f64,
+            FieldType::F64 => f64::from_bits(raw),
+            _ => {
+                let value = if self.field_type.is_signed() && bit_len < 64 && raw >> (bit_len - 1) & 0x01 == 1 {
+                    raw as i64 - (1_i64 << bit_len)
+                } else {
+                    raw as i64
+                };
+                if let Some(name) = self.enum_names.get(&value) {
+                    return Ok(name.as_str().into());
+                }
+                if self.scale.is_none() {
+                    return Ok(if self.field_type.is_signed() { value.into() } else { raw.into() });
+                }
+                value as f64
+            }
+        };
+        Ok(float(value * self.scale.unwrap_or(1.0)))
+    }
+}
+
+// 枚举的值可以是十进制或 0x 开头的十六进制
+fn parse_integer(text: &str) -> Option<i64> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::struct_decoder::*;
+
+    const LAYOUT: &str = r#"
+[[struct_field]]
+name = "id"
+offset = 0
+type = "u16"
+
+[[struct_field]]
+name = "temperature"
+offset = 2
+type = "i16"
+scale = 0.01
+
+[[struct_field]]
+name = "pressure"
+offset = 4
+type = "u32"
+endian = "big"
+
+[[struct_field]]
+name = "state"
+offset = 8
+type = "u8"
+bit_offset = 0
+bit_len = 4
+enum = { 0 = "idle", 1 = "running", 0x0f = "fault" }
+
+[[struct_field]]
+name = "low_battery"
+offset = 8
+type = "bool"
+bit_offset = 7
+
+[[struct_field]]
+name = "voltage"
+offset = 9
+type = "f32"
+
+[[struct_field]]
+name = "name"
+offset = 13
+type = "string"
+len = 6
+"#;
+
+    #[derive(serde_derive::Deserialize)]
+    struct Layout {
+        struct_field: Vec<StructFieldConfig>,
+    }
+
+    fn layout(toml_str: &str) -> Result<StructLayout, String> {
+        let layout: Layout = toml::from_str(toml_str).unwrap();
+        StructLayout::new(&layout.struct_field)
+    }
+
+    #[test]
+    fn struct_decode() {
+        let layout = layout(LAYOUT).unwrap();
+        let mut frame = vec![0x01, 0x00, 0x46, 0xf5, 0x00, 0x01, 0x8a, 0x92, 0x81];
+        frame.extend_from_slice(&3.88_f32.to_le_bytes());
+        frame.extend_from_slice(b"SN-1\0\0");
+        let decoded = layout.decode(&frame).unwrap();
+        assert_eq!(decoded["id"], 1);
+        assert_eq!(decoded["temperature"], -27.46);
+        assert_eq!(decoded["pressure"], 101010);
+        assert_eq!(decoded["state"], "running");
+        assert_eq!(decoded["low_battery"], true);
+        assert_eq!(decoded["voltage"].as_f64().unwrap() as f32, 3.88);
+        assert_eq!(decoded["name"], "SN-1");
+
+        // 没有映射的枚举值输出原始值
+        frame[8] = 0x05;
+        let decoded = layout.decode(&frame).unwrap();
+        assert_eq!(decoded["state"], 5);
+        assert_eq!(decoded["low_battery"], false);
+        frame[8] = 0x0f;
+        assert_eq!(layout.decode(&frame).unwrap()["state"], "fault");
+
+        assert!(layout.decode(&frame[..18]).is_err());
+        frame[13] = 0xff;
+        assert!(layout.decode(&frame).is_err());
+    }
+
+    #[test]
+    fn struct_layout_check() {
+        let field = "[[struct_field]]\nname = \"a\"\noffset = 0\n";
+        assert!(layout(&format!("{}type = \"u8\"\nbit_offset = 4\nbit_len = 5", field)).is_err());
+        assert!(layout(&format!("{}type = \"f32\"\nbit_offset = 4", field)).is_err());
+        assert!(layout(&format!("{}type = \"bytes\"", field)).is_err());
+        assert!(layout(&format!("{}type = \"u8\"\nlen = 2", field)).is_err());
+        assert!(layout(&format!("{}type = \"string\"\nlen = 2\nscale = 0.1", field)).is_err());
+        assert!(layout(&format!("{}type = \"u8\"\nenum = {{ a = \"idle\" }}", field)).is_err());
+        assert!(layout(&format!("{}type = \"u8\"\n{}type = \"u8\"", field, field)).is_err());
+        assert!(layout("struct_field = []").is_err());
+
+        let layout = layout(&format!("{}type = \"i16\"\nbit_offset = 4\nbit_len = 8", field)).unwrap();
+        assert_eq!(layout.decode(&[0xf0, 0x0f]).unwrap()["a"], -1);
+        assert_eq!(layout.decode(&[0x10, 0x00, 0x99]).unwrap()["a"], 1);
+    }
+}
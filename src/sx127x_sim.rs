@@ -0,0 +1,234 @@
+//! SX127x 寄存器级模拟器，实现了 `RegisterAccess`，用于在没有射频模块的机器上测试 `SpiIf`。
+//!
+//! 模拟了 LoRa 模式下的 FIFO、IRQ 标志和工作模式：进入发送模式时把 FIFO 中的报文记录下来并置位
+//! TX_DONE，然后回到待机模式；处于连续接收模式时可以用 `receive` 注入报文。
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use crate::interface::RegisterAccess;
+use crate::interface::{REG_FEI_LSB, REG_FEI_MID, REG_FEI_MSB, REG_FIFO, REG_FIFO_ADDR_PTR, REG_FIFO_RX_BASE_AD};
+use crate::interface::{REG_FIFO_RX_CURRENT_ADDR, REG_FIFO_TX_BASE_AD, REG_FRF_LSB, REG_FRF_MID, REG_FRF_MSB};
+use crate::interface::{REG_IRQ_FLAGS, REG_OPMODE, REG_PAYLOAD_LENGTH, REG_PKT_RSSI_VALUE, REG_PKT_SNR_VALUE};
+use crate::interface::{REG_RX_NB_BYTES, REG_VERSION};
+use crate::interface::{PAYLOAD_CRC_ERROR, RX_DONE, SX72_LONG_RANGE_MODE, SX72_MODE_RX_CONTINUOS, SX72_MODE_SLEEP};
+use crate::interface::{SX72_MODE_STANDBY, SX72_MODE_TX, TX_DONE, VALID_HEADER};
+
+const MODE_MASK: u8 = 0x07;
+
+struct State {
+    registers: [u8; 0x80],
+    fifo: [u8; 256],
+    sent: Vec<Vec<u8>>,
+}
+
+/// SX127x 模拟器，克隆后共享同一个芯片的状态
+#[derive(Clone)]
+pub struct Sx127xSim {
+    state: Rc<RefCell<State>>,
+}
+
+impl Sx127xSim {
+    pub fn new() -> Self {
+        Sx127xSim::with_version(0x12)
+    }
+
+    pub fn with_version(version: u8) -> Self {
+        let mut registers = [0_u8; 0x80];
+        // 上电后的默认值：FSK 模式、待机，434 MHz
+        registers[REG_OPMODE as usize] = 0x09;
+        registers[REG_FRF_MSB as usize] = 0x6C;
+        registers[REG_FRF_MID as usize] = 0x80;
+        registers[REG_VERSION as usize] = version;
+        Sx127xSim {
+            state: Rc::new(RefCell::new(State {
+                registers,
+                fifo: [0_u8; 256],
+                sent: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn register(&self, addr: u8) -> u8 {
+        self.state.borrow().registers[(addr & 0x7f) as usize]
+    }
+
+    pub fn is_lora(&self) -> bool {
+        self.register(REG_OPMODE) & SX72_LONG_RANGE_MODE != 0
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.register(REG_OPMODE) & MODE_MASK
+    }
+
+    /// 当前的载波频率，单位：Hz
+    pub fn frequency(&self) -> u32 {
+        let frf = ((self.register(REG_FRF_MSB) as u64) << 16)
+            | ((self.register(REG_FRF_MID) as u64) << 8)
+            | self.register(REG_FRF_LSB) as u64;
+        ((frf * 32_000_000) >> 19) as u32
+    }
+
+    /// 已发送的报文（包括报头）
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.state.borrow().sent.clone()
+    }
+
+    /// 模拟收到一个报文，不在 LoRa 连续接收模式时丢弃并返回 false
+    pub fn receive(&self, packet: &[u8], pkt_rssi: u8, pkt_snr: i8, fei: i32) -> bool {
+        if !self.is_lora() || self.mode() != SX72_MODE_RX_CONTINUOS {
+            return false;
+        }
+        let mut state = self.state.borrow_mut();
+        let base = state.registers[REG_FIFO_RX_BASE_AD as usize];
+        for (i, byte) in packet.iter().enumerate() {
+            state.fifo[base.wrapping_add(i as u8) as usize] = *byte;
+        }
+        let registers = &mut state.registers;
+        registers[REG_FIFO_RX_CURRENT_ADDR as usize] = base;
+        registers[REG_RX_NB_BYTES as usize] = packet.len() as u8;
+        registers[REG_PKT_RSSI_VALUE as usize] = pkt_rssi;
+        registers[REG_PKT_SNR_VALUE as usize] = pkt_snr as u8;
+        registers[REG_FEI_MSB as usize] = ((fei >> 16) & 0x0F) as u8;
+        registers[REG_FEI_MID as usize] = (fei >> 8) as u8;
+        registers[REG_FEI_LSB as usize] = fei as u8;
+        registers[REG_IRQ_FLAGS as usize] |= RX_DONE | VALID_HEADER;
+        true
+    }
+
+    /// 模拟收到一个 CRC 错误的报文
+    pub fn receive_crc_error(&self) {
+        self.state.borrow_mut().registers[REG_IRQ_FLAGS as usize] |= RX_DONE | VALID_HEADER | PAYLOAD_CRC_ERROR;
+    }
+
+    fn transmit(state: &mut State) {
+        let base = state.registers[REG_FIFO_TX_BASE_AD as usize];
+        let len = state.registers[REG_PAYLOAD_LENGTH as usize];
+        let packet = (0..len).map(|i| state.fifo[base.wrapping_add(i) as usize]).collect();
+        state.sent.push(packet);
+        state.registers[REG_IRQ_FLAGS as usize] |= TX_DONE;
+        // 发送完成后自动回到待机模式
+        let op_mode = state.registers[REG_OPMODE as usize];
+        state.registers[REG_OPMODE as usize] = (op_mode & !MODE_MASK) | SX72_MODE_STANDBY;
+    }
+}
+
+impl RegisterAccess for Sx127xSim {
+    fn read_register(&mut self, addr: u8) -> io::Result<u8> {
+        let mut state = self.state.borrow_mut();
+        let addr = addr & 0x7f;
+        if addr == REG_FIFO {
+            let ptr = state.registers[REG_FIFO_ADDR_PTR as usize];
+            state.registers[REG_FIFO_ADDR_PTR as usize] = ptr.wrapping_add(1);
+            return Ok(state.fifo[ptr as usize]);
+        }
+        Ok(state.registers[addr as usize])
+    }
+
+    fn write_register(&mut self, addr: u8, value: u8) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        let addr = addr & 0x7f;
+        match addr {
+            REG_FIFO => {
+                let ptr = state.registers[REG_FIFO_ADDR_PTR as usize];
+                state.registers[REG_FIFO_ADDR_PTR as usize] = ptr.wrapping_add(1);
+                state.fifo[ptr as usize] = value;
+            }
+            // 写 1 清除
+            REG_IRQ_FLAGS => state.registers[addr as usize] &= !value,
+            REG_OPMODE => {
+                let op_mode = state.registers[addr as usize];
+                // LongRangeMode 只能在进入睡眠模式时修改，切换到其他模式时保持不变
+                let value = if value & MODE_MASK == SX72_MODE_SLEEP {
+                    value
+                } else {
+                    (value & !SX72_LONG_RANGE_MODE) | (op_mode & SX72_LONG_RANGE_MODE)
+                };
+                state.registers[addr as usize] = value;
+                if value & SX72_LONG_RANGE_MODE != 0 && value & MODE_MASK == SX72_MODE_TX {
+                    Sx127xSim::transmit(&mut state);
+                }
+            }
+            // 只读寄存器
+            REG_VERSION | REG_RX_NB_BYTES | REG_FIFO_RX_CURRENT_ADDR | REG_PKT_RSSI_VALUE | REG_PKT_SNR_VALUE => {}
+            _ => state.registers[addr as usize] = value,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interface::*;
+    use crate::sx127x_sim::Sx127xSim;
+    use std::time::Duration;
+
+    fn setup(config: &LoraConfig) -> Sx127xSim {
+        let mut sim = Sx127xSim::new();
+        SpiIf.init(&mut sim, config).unwrap();
+        sim
+    }
+
+    #[test]
+    fn sim_setup_lora() {
+        let config = LoraConfig { frequency: 868_100_000, spreading_factor: 9, sync_word: 0x34, ..LoraConfig::default() };
+        let sim = setup(&config);
+        assert!(sim.is_lora());
+        assert_eq!(sim.mode(), SX72_MODE_RX_CONTINUOS);
+        // FSTEP 约为 61 Hz
+        assert!((sim.frequency() as i64 - config.frequency as i64).abs() < 62);
+        let [config1, config2, config3] = config.modem_config();
+        assert_eq!(sim.register(REG_MODEM_CONFIG), config1);
+        assert_eq!(sim.register(REG_MODEM_CONFIG2), config2);
+        assert_eq!(sim.register(REG_MODEM_CONFIG3), config3);
+        assert_eq!(sim.register(REG_SYNC_WORD), 0x34);
+        assert_eq!(sim.register(REG_PREAMBLE_LSB), 8);
+        assert_eq!(sim.register(REG_PA_CONFIG), PA_SELECT | 8);
+
+        let mut sim = Sx127xSim::with_version(0x00);
+        assert!(SpiIf.init(&mut sim, &config).is_err());
+    }
+
+    #[test]
+    fn sim_rx() {
+        let config = LoraConfig { frequency: 868_100_000, ..LoraConfig::default() };
+        let mut sim = setup(&config);
+        assert!(SpiIf.read(&mut sim, &config).is_err());
+
+        let mut packet = vec![0xFF, 0x03, 0x07, 0x00];
+        packet.extend_from_slice(b"{\"t\": 27.45}");
+        assert!(sim.receive(&packet, 60, 38, -200));
+        let received = SpiIf.read(&mut sim, &config).unwrap();
+        assert_eq!(received.header, RhHeader { to: 0xFF, from: 0x03, id: 0x07, flags: 0x00 });
+        assert_eq!(received.payload, b"{\"t\": 27.45}".to_vec());
+        assert_eq!(received.rssi, -93);
+        assert_eq!(received.snr, 9.5);
+        assert_eq!(received.freq_error, -26);
+        // 中断标志已清除
+        assert_eq!(sim.register(REG_IRQ_FLAGS), 0);
+        assert!(SpiIf.read(&mut sim, &config).is_err());
+
+        sim.receive_crc_error();
+        assert!(SpiIf.read(&mut sim, &config).is_err());
+        assert_eq!(sim.register(REG_IRQ_FLAGS), 0);
+
+        // 少于 4 字节的报文
+        assert!(sim.receive(&[0x01, 0x02], 60, 38, 0));
+        assert!(SpiIf.read(&mut sim, &config).is_err());
+    }
+
+    #[test]
+    fn sim_tx() {
+        let config = LoraConfig::default();
+        let mut sim = setup(&config);
+        let header = RhHeader { to: 0xFF, from: 0x01, id: 0x02, flags: 0x00 };
+        SpiIf.send(&mut sim, &header, b"on", Duration::from_millis(100), None).unwrap();
+        assert_eq!(sim.sent(), vec![vec![0xFF, 0x01, 0x02, 0x00, b'o', b'n']]);
+        // 发送完成后回到接收模式
+        assert_eq!(sim.mode(), SX72_MODE_RX_CONTINUOS);
+        assert_eq!(sim.register(REG_IRQ_FLAGS), 0);
+
+        let payload = vec![0_u8; RH_MAX_PAYLOAD_LEN + 1];
+        assert!(SpiIf.send(&mut sim, &header, &payload, Duration::from_millis(100), None).is_err());
+    }
+}
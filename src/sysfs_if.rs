@@ -0,0 +1,499 @@
+//! Linux sysfs 传感器数据接口：定时读取 1-Wire（DS18B20 等）、IIO、hwmon 传感器，
+//! 每次轮询把所有传感器的值输出为一个 JSON 对象，对应 `[[data_if.sensor]]`。
+//!
+//! `if_name` 为 sysfs 的挂载点（一般为 `/sys`），测试时可以指向一个模拟的目录树。
+
+extern crate log;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use serde_derive::Deserialize;
+use crate::data_source::{DataIfError, DataSource, Health, LogEvent};
+use crate::types::{DataIfConfig, OriginalData};
+
+// 默认轮询周期，DS18B20 一次转换需要 750 ms
+pub const DEFAULT_SYSFS_POLL_INTERVAL_MS: u64 = 10000;
+
+const W1_DEVICES: &str = "bus/w1/devices";
+const IIO_DEVICES: &str = "bus/iio/devices";
+const HWMON_DEVICES: &str = "class/hwmon";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorType {
+    // /sys/bus/w1/devices/<device>/w1_slave
+    W1,
+    // /sys/bus/iio/devices/<device>/in_<channel>_raw
+    Iio,
+    // /sys/class/hwmon/<device>/<channel>_input
+    Hwmon,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SensorConfig {
+    // 输出的 JSON 属性名，w1 默认为设备目录名，读取所有 1-Wire 设备时为前缀
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub sensor_type: SensorType,
+    // 设备目录名（例如 "28-0316a2795dff"、"iio:device0"、"hwmon1"）或设备 name 属性的值（例如 "cpu_thermal"），
+    // w1 不配置时读取所有 1-Wire 设备
+    pub device: Option<String>,
+    // iio、hwmon 的通道，例如 "temp"、"humidityrelative"、"voltage0"、"temp1"
+    pub channel: Option<String>,
+    // 在默认单位（℃、%RH、V、A、W）的基础上再乘以 scale
+    pub scale: Option<f64>,
+}
+
+impl SensorConfig {
+    // 用于日志和错误统计的传感器名称
+    fn label(&self) -> String {
+        match (&self.name, &self.device) {
+            (Some(name), _) => name.clone(),
+            (None, Some(device)) => device.clone(),
+            (None, None) => String::from("w1"),
+        }
+    }
+}
+
+// 通道类型，即通道名开头的字母部分，例如 "voltage0" 为 "voltage"，"temp_object" 为 "temp"
+fn channel_type(channel: &str) -> &str {
+    let end = channel.find(|c: char| !c.is_ascii_lowercase()).unwrap_or(channel.len());
+    &channel[..end]
+}
+
+// 把 sysfs 的单位（毫摄氏度、毫伏等）换算为 ℃、%RH、V、A、W
+fn unit_scale(sensor_type: SensorType, channel: &str) -> f64 {
+    match (sensor_type, channel_type(channel)) {
+        (SensorType::Iio, "temp" | "humidityrelative" | "voltage" | "current") => 0.001,
+        (SensorType::Hwmon, "temp" | "in" | "curr" | "humidity") => 0.001,
+        (SensorType::Hwmon, "power" | "energy") => 0.000001,
+        _ => 1.0,
+    }
+}
+
+// 去掉换算时产生的浮点误差
+fn round(value: f64) -> f64 {
+    (value * 1e6).round() / 1e6
+}
+
+fn read_attr(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path)
+        .map(|text| text.trim().to_string())
+        .map_err(|err| format!("read {} failed: {}", path.display(), err))
+}
+
+fn read_number(path: &Path) -> Result<f64, String> {
+    let text = read_attr(path)?;
+    text.parse().map_err(|_| format!("invalid value in {}: {}", path.display(), text))
+}
+
+/// 解析 w1_slave 的内容，返回温度（℃）。第一行以 "YES" 结尾表示 CRC 正确，第二行的 "t=" 后为毫摄氏度
+pub fn parse_w1_slave(text: &str) -> Result<f64, String> {
+    let mut lines = text.lines();
+    match lines.next() {
+        Some(line) if line.trim_end().ends_with("YES") => {}
+        _ => return Err(String::from("w1 crc check failed")),
+    }
+    let millis = lines.next()
+        .and_then(|line| line.rsplit("t=").next().filter(|_| line.contains("t=")))
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .ok_or_else(|| String::from("no temperature in w1_slave"))?;
+    Ok(millis as f64 / 1000.0)
+}
+
+// 传感器的属性名和读取结果
+type Reading = (String, Result<f64, String>);
+
+/// 按配置定时读取 sysfs 中的传感器
+pub struct SysfsSource {
+    root: PathBuf,
+    sensors: Vec<SensorConfig>,
+    poll_interval: Duration,
+    last_poll: Option<Instant>,
+    // 每个传感器（自动发现的 1-Wire 设备按输出的属性名）最近一次读取的错误
+    errors: HashMap<String, String>,
+    // 最近一次轮询读取成功的值的个数
+    values: usize,
+    events: Vec<LogEvent>,
+}
+
+impl SysfsSource {
+    pub fn build(config: &DataIfConfig) -> Result<Box<dyn DataSource>, DataIfError> {
+        let config_error = |err: String| {
+            error!("{}: {}", config.if_name, err);
+            DataIfError::DataIfConfigError(err)
+        };
+        let sensors = match &config.sensor {
+            Some(sensors) if !sensors.is_empty() => sensors.clone(),
+            _ => return Err(config_error(String::from("no sensor configured, please add [[data_if.sensor]]"))),
+        };
+        let mut labels: Vec<String> = Vec::new();
+        for sensor in sensors.iter() {
+            match (sensor.sensor_type, &sensor.device, &sensor.channel) {
+                (SensorType::W1, _, Some(_)) => return Err(config_error(format!("sensor {}: w1 has no channel", sensor.label()))),
+                (SensorType::W1, _, None) => {}
+                (SensorType::Iio | SensorType::Hwmon, Some(_), Some(_)) if sensor.name.is_some() => {}
+                _ => return Err(config_error(format!("sensor {}: name, device and channel are required", sensor.label()))),
+            }
+            if labels.contains(&sensor.label()) {
+                return Err(config_error(format!("duplicate sensor: {}", sensor.label())));
+            }
+            labels.push(sensor.label());
+        }
+        Ok(Box::new(SysfsSource {
+            root: PathBuf::from(&config.if_name),
+            sensors,
+            poll_interval: Duration::from_millis(config.poll_interval_ms.unwrap_or(DEFAULT_SYSFS_POLL_INTERVAL_MS)),
+            last_poll: None,
+            errors: HashMap::new(),
+            values: 0,
+            events: Vec::new(),
+        }))
+    }
+
+    // 设备目录，先按目录名查找，再按目录下 name 属性的值查找
+    fn device_dir(&self, class: &str, device: &str) -> Result<PathBuf, String> {
+        let dir = self.root.join(class);
+        let path = dir.join(device);
+        if path.is_dir() {
+            return Ok(path);
+        }
+        let entries = fs::read_dir(&dir).map_err(|err| format!("read {} failed: {}", dir.display(), err))?;
+        let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+        paths.sort();
+        paths.into_iter()
+            .find(|path| matches!(read_attr(&path.join("name")), Ok(name) if name == device))
+            .ok_or_else(|| format!("device {} not found in {}", device, dir.display()))
+    }
+
+    fn read_w1(&self, device: &str) -> Result<f64, String> {
+        let path = self.root.join(W1_DEVICES).join(device).join("w1_slave");
+        parse_w1_slave(&read_attr(&path)?)
+    }
+
+    // 所有有 w1_slave 的 1-Wire 设备
+    fn w1_devices(&self) -> Result<Vec<String>, String> {
+        let dir = self.root.join(W1_DEVICES);
+        let entries = fs::read_dir(&dir).map_err(|err| format!("read {} failed: {}", dir.display(), err))?;
+        let mut devices: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("w1_slave").is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        devices.sort();
+        Ok(devices)
+    }
+
+    // IIO 的值 = (raw + offset) * scale，有 in_<channel>_input 时直接使用。scale、offset 可以是通道类型共用的
+    fn read_iio(&self, device: &str, channel: &str) -> Result<f64, String> {
+        let dir = self.device_dir(IIO_DEVICES, device)?;
+        let input = dir.join(format!("in_{}_input", channel));
+        if input.is_file() {
+            return read_number(&input);
+        }
+        let attr = |suffix: &str, default: f64| -> Result<f64, String> {
+            for name in [channel, channel_type(channel)].iter() {
+                let path = dir.join(format!("in_{}_{}", name, suffix));
+                if path.is_file() {
+                    return read_number(&path);
+                }
+            }
+            Ok(default)
+        };
+        let raw = read_number(&dir.join(format!("in_{}_raw", channel)))?;
+        Ok((raw + attr("offset", 0.0)?) * attr("scale", 1.0)?)
+    }
+
+    fn read_hwmon(&self, device: &str, channel: &str) -> Result<f64, String> {
+        let dir = self.device_dir(HWMON_DEVICES, device)?;
+        read_number(&dir.join(format!("{}_input", channel)))
+    }
+
+    // 读取一个传感器，返回 (属性名, 读取结果)。没有指定 device 的 w1 传感器每个设备单独返回读取结果，
+    // 一个探头拔出或校验失败时不影响其他探头
+    fn read_sensor(&self, sensor: &SensorConfig) -> Result<Vec<Reading>, String> {
+        let values = match (sensor.sensor_type, &sensor.device, &sensor.channel) {
+            (SensorType::W1, Some(device), _) => vec![(sensor.label(), self.read_w1(device))],
+            (SensorType::W1, None, _) => {
+                let mut values = Vec::new();
+                for device in self.w1_devices()? {
+                    let name = match &sensor.name {
+                        Some(name) => format!("{}_{}", name, device),
+                        None => device.clone(),
+                    };
+                    values.push((name, self.read_w1(&device)));
+                }
+                values
+            }
+            (SensorType::Iio, Some(device), Some(channel)) => {
+                vec![(sensor.label(), self.read_iio(device, channel).map(|value| value * unit_scale(SensorType::Iio, channel)))]
+            }
+            (SensorType::Hwmon, Some(device), Some(channel)) => {
+                vec![(sensor.label(), self.read_hwmon(device, channel).map(|value| value * unit_scale(SensorType::Hwmon, channel)))]
+            }
+            _ => return Err(String::from("device and channel are required")),
+        };
+        let scale = sensor.scale.unwrap_or(1.0);
+        Ok(values.into_iter().map(|(name, value)| (name, value.map(|value| round(value * scale)))).collect())
+    }
+
+    fn log_event(event: &str, sensor: &str, message: String) -> LogEvent {
+        let mut fields = json::JsonValue::new_object();
+        fields["sensor"] = sensor.into();
+        LogEvent {
+            event: event.to_string(),
+            message,
+            fields,
+        }
+    }
+
+    /// 到了轮询时间时读取所有传感器，读取失败的传感器不输出
+    fn poll(&mut self) -> Option<json::JsonValue> {
+        if let Some(last_poll) = self.last_poll {
+            if last_poll.elapsed() < self.poll_interval {
+                return None;
+            }
+        }
+        self.last_poll = Some(Instant::now());
+        let mut obj = json::JsonValue::new_object();
+        let mut failed: Vec<(String, String)> = Vec::new();
+        for sensor in self.sensors.iter() {
+            match self.read_sensor(sensor) {
+                Ok(values) => {
+                    for (name, value) in values {
+                        match value {
+                            Ok(value) => obj[name.as_str()] = value.into(),
+                            Err(err) => failed.push((name, err)),
+                        }
+                    }
+                }
+                Err(err) => failed.push((sensor.label(), err)),
+            }
+        }
+        // 这次没有失败的传感器（包括已经移除的 1-Wire 设备）视为恢复
+        let mut recovered: Vec<&String> = self.errors.keys().filter(|label| !failed.iter().any(|(name, _)| name == *label)).collect();
+        recovered.sort();
+        for label in recovered {
+            info!("sensor {} recovered", label);
+            self.events.push(SysfsSource::log_event("sysfs_read_recovered", label, format!("sensor {} recovered", label)));
+        }
+        for (label, err) in failed.iter() {
+            // 错误变化时才产生日志事件，避免每次轮询都发布
+            if self.errors.get(label) != Some(err) {
+                warn!("read sensor {} failed: {}", label, err);
+                let mut event = SysfsSource::log_event("sysfs_read_error", label, format!("read sensor {} failed: {}", label, err));
+                event.fields["error"] = err.clone().into();
+                self.events.push(event);
+            } else {
+                debug!("read sensor {} failed: {}", label, err);
+            }
+        }
+        self.errors = failed.into_iter().collect();
+        self.values = obj.len();
+        if obj.is_empty() { None } else { Some(obj) }
+    }
+}
+
+impl DataSource for SysfsSource {
+    fn open(&mut self) -> Result<(), DataIfError> {
+        if self.root.is_dir() {
+            Ok(())
+        } else {
+            error!("Open {} failed: not a directory", self.root.display());
+            Err(DataIfError::DataIfOpenError)
+        }
+    }
+
+    fn read(&mut self) -> Result<Vec<OriginalData>, DataIfError> {
+        Ok(self.poll().map(|obj| OriginalData::new(&obj.dump())).into_iter().collect())
+    }
+
+    fn write(&mut self, msg: &str) -> Result<(), DataIfError> {
+        debug!("sysfs is read only, drop msg: {}", msg);
+        Ok(())
+    }
+
+    fn health(&self) -> Health {
+        if self.errors.is_empty() {
+            Health::Up
+        } else if self.values == 0 {
+            Health::Down(String::from("all sensors failed"))
+        } else {
+            let mut sensors: Vec<&str> = self.errors.keys().map(|sensor| sensor.as_str()).collect();
+            sensors.sort();
+            Health::Degraded(format!("sensor {} failed", sensors.join(", ")))
+        }
+    }
+
+    fn log_events(&mut self) -> Vec<LogEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::data_source::{DataSource, Health};
+    use crate::sysfs_if::*;
+
+    fn write(root: &Path, path: &str, text: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    // 模拟的 sysfs 目录树
+    fn fake_sysfs(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("gw_sysfs_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        write(&root, "bus/w1/devices/28-0316a2795dff/w1_slave",
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n");
+        write(&root, "bus/w1/devices/28-0416b3896eaa/w1_slave",
+            "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n50 05 4b 46 7f ff 0c 10 1c t=-1250\n");
+        fs::create_dir_all(root.join("bus/w1/devices/w1_bus_master1")).unwrap();
+        write(&root, "bus/iio/devices/iio:device0/name", "hdc100x\n");
+        write(&root, "bus/iio/devices/iio:device0/in_temp_raw", "26214\n");
+        write(&root, "bus/iio/devices/iio:device0/in_temp_scale", "2.517700195\n");
+        write(&root, "bus/iio/devices/iio:device0/in_temp_offset", "-15887.515151\n");
+        write(&root, "bus/iio/devices/iio:device0/in_humidityrelative_raw", "32768\n");
+        write(&root, "bus/iio/devices/iio:device0/in_humidityrelative_scale", "1.525878906\n");
+        write(&root, "bus/iio/devices/iio:device1/in_voltage0_raw", "1000\n");
+        write(&root, "bus/iio/devices/iio:device1/in_voltage_scale", "0.5\n");
+        write(&root, "class/hwmon/hwmon0/name", "cpu_thermal\n");
+        write(&root, "class/hwmon/hwmon0/temp1_input", "48312\n");
+        write(&root, "class/hwmon/hwmon1/name", "ina219\n");
+        write(&root, "class/hwmon/hwmon1/power1_input", "1250000\n");
+        root
+    }
+
+    fn build_source(root: &Path, sensors: &str) -> Result<Box<dyn DataSource>, DataIfError> {
+        let config = format!("if_name = \"{}\"\nif_type = \"sysfs\"\n{}", root.display(), sensors);
+        SysfsSource::build(&toml::from_str(&config).unwrap())
+    }
+
+    #[test]
+    fn w1_slave() {
+        let text = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(text).unwrap(), 23.125);
+        assert!(parse_w1_slave(&text.replace("YES", "NO")).is_err());
+        assert!(parse_w1_slave("00 : crc=00 YES\n").is_err());
+    }
+
+    #[test]
+    fn sysfs_poll() {
+        let root = fake_sysfs("poll");
+        let sensors = r#"
+[[sensor]]
+name = "water"
+type = "w1"
+device = "28-0316a2795dff"
+
+[[sensor]]
+name = "probe"
+type = "w1"
+
+[[sensor]]
+name = "temperature"
+type = "iio"
+device = "hdc100x"
+channel = "temp"
+
+[[sensor]]
+name = "humidity"
+type = "iio"
+device = "iio:device0"
+channel = "humidityrelative"
+
+[[sensor]]
+name = "battery"
+type = "iio"
+device = "iio:device1"
+channel = "voltage0"
+
+[[sensor]]
+name = "cpu"
+type = "hwmon"
+device = "cpu_thermal"
+channel = "temp1"
+
+[[sensor]]
+name = "power_mw"
+type = "hwmon"
+device = "hwmon1"
+channel = "power1"
+scale = 1000.0
+"#;
+        let mut source = build_source(&root, sensors).unwrap();
+        source.open().unwrap();
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs.len(), 1);
+        let obj = json::parse(&msgs[0].msg).unwrap();
+        assert_eq!(obj["water"], 23.125);
+        assert_eq!(obj["probe_28-0316a2795dff"], 23.125);
+        assert_eq!(obj["probe_28-0416b3896eaa"], -1.25);
+        assert_eq!(obj["temperature"], 25.998993);
+        assert_eq!(obj["humidity"], 50.0);
+        assert_eq!(obj["battery"], 0.5);
+        assert_eq!(obj["cpu"], 48.312);
+        assert_eq!(obj["power_mw"], 1250.0);
+        assert_eq!(source.health(), Health::Up);
+        // 没到轮询时间
+        assert!(source.read().unwrap().is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn sysfs_errors() {
+        let root = fake_sysfs("errors");
+        let sensors = "poll_interval_ms = 0\n[[sensor]]\nname = \"cpu\"\ntype = \"hwmon\"\ndevice = \"cpu_thermal\"\nchannel = \"temp1\"\n\
+            [[sensor]]\nname = \"gpu\"\ntype = \"hwmon\"\ndevice = \"gpu_thermal\"\nchannel = \"temp1\"\n";
+        let mut source = build_source(&root, sensors).unwrap();
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs[0].msg, r#"{"cpu":48.312}"#);
+        assert_eq!(source.health(), Health::Degraded(String::from("sensor gpu failed")));
+        let events = source.log_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "sysfs_read_error");
+        assert_eq!(events[0].fields["sensor"], "gpu");
+        // 相同的错误不重复产生日志事件
+        source.read().unwrap();
+        assert!(source.log_events().is_empty());
+
+        write(&root, "class/hwmon/hwmon2/name", "gpu_thermal\n");
+        write(&root, "class/hwmon/hwmon2/temp1_input", "51000\n");
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs[0].msg, r#"{"cpu":48.312,"gpu":51}"#);
+        assert_eq!(source.log_events()[0].event, "sysfs_read_recovered");
+        assert_eq!(source.health(), Health::Up);
+
+        // 一个 1-Wire 探头校验失败时，其他探头的数据照常输出
+        write(&root, "bus/w1/devices/28-0516c4907fbb/w1_slave",
+            "50 05 4b 46 7f ff 0c 10 1c : crc=1c NO\n50 05 4b 46 7f ff 0c 10 1c t=85000\n");
+        let mut source = build_source(&root, "poll_interval_ms = 0\n[[sensor]]\nname = \"probe\"\ntype = \"w1\"\n").unwrap();
+        let msgs = source.read().unwrap();
+        assert_eq!(msgs[0].msg, r#"{"probe_28-0316a2795dff":23.125,"probe_28-0416b3896eaa":-1.25}"#);
+        assert_eq!(source.health(), Health::Degraded(String::from("sensor probe_28-0516c4907fbb failed")));
+        let events = source.log_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["sensor"], "probe_28-0516c4907fbb");
+        // 探头移除后不再是错误
+        fs::remove_dir_all(root.join("bus/w1/devices/28-0516c4907fbb")).unwrap();
+        source.read().unwrap();
+        assert_eq!(source.log_events()[0].event, "sysfs_read_recovered");
+        assert_eq!(source.health(), Health::Up);
+
+        fs::remove_dir_all(root.join("bus/w1/devices")).unwrap();
+        assert!(source.read().unwrap().is_empty());
+        assert_eq!(source.health(), Health::Down(String::from("all sensors failed")));
+
+        assert!(build_source(&root, "[[sensor]]\nname = \"a\"\ntype = \"iio\"\ndevice = \"iio:device0\"").is_err());
+        assert!(build_source(&root, "[[sensor]]\ntype = \"w1\"\ndevice = \"28-0316a2795dff\"\nchannel = \"temp\"").is_err());
+        assert!(build_source(&root, "[[sensor]]\nname = \"a\"\ntype = \"w1\"\n[[sensor]]\nname = \"a\"\ntype = \"w1\"").is_err());
+        assert!(build_source(&root, "").is_err());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -1,6 +1,13 @@
 extern crate paho_mqtt;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
 use std::sync::mpsc::Receiver;
+use crate::interface::{LoraConfig, SerialSettings};
+use crate::modbus::DeviceConfig;
+use crate::can_if::{CanFilterConfig, CanMessageConfig};
+use crate::min_message::MinMessageConfig;
+use crate::struct_decoder::StructFieldConfig;
+use crate::sysfs_if::SensorConfig;
 
 pub type MsgReceiver = Receiver<Option<paho_mqtt::Message>>;
 
@@ -23,4 +30,142 @@ pub struct TopicConfig {
 pub struct TlsFiles {
     pub cafile: String,
     pub key_store: String,
+}
+
+// 数据接口收到的原始数据
+#[derive(Debug)]
+pub struct OriginalData {
+    pub msg: String,
+    // 对端地址（TCP/UDP 接口）
+    pub peer: Option<String>,
+    // 数据接口名称
+    pub if_name: String,
+    // 数据接口提供的调用模型的值（见 INTERFACE_CALLS），例如 LoRa 报文的 RSSI
+    pub calls: HashMap<String, json::JsonValue>,
+    // 该条数据使用的数据模板，默认使用数据接口的数据模板
+    pub template: Option<String>,
+}
+
+// 由数据接口提供值的调用模型，数据接口没有提供时替换为 null
+pub const INTERFACE_CALLS: [&str; 10] = [
+    "PEER", "RSSI", "SNR", "FREQ_ERR", "LORA_TO", "LORA_FROM", "LORA_ID", "LORA_FLAGS", "MIN_ID", "MIN_KIND",
+];
+
+impl OriginalData {
+    pub fn new(msg: &str) -> Self {
+        OriginalData {
+            msg: String::from(msg),
+            peer: None,
+            if_name: String::new(),
+            calls: HashMap::new(),
+            template: None,
+        }
+    }
+}
+
+// 下行消息，topic 为收到该消息的 MQTT 主题
+#[derive(Debug, Clone)]
+pub struct DownstreamMsg {
+    pub topic: String,
+    pub payload: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DataIfConfig {
+    // 数据接口名称，默认为 if_type，多个数据接口时不能重复
+    pub name: Option<String>,
+    pub if_name: String,
+    pub if_type: String,
+    // text_file 读取位置的保存文件，默认为 if_name + ".offset"
+    pub offset_file: Option<String>,
+    // tcp_server 允许的最大连接数
+    pub max_connections: Option<usize>,
+    // 该数据接口使用的数据模板，默认使用 msg.template
+    pub template: Option<String>,
+    // 启动时校验 template 使用的原始数据示例，没有配置时使用 msg.example，但配置了 template 时不校验
+    pub example: Option<String>,
+    // 载荷解码器："json"、"text"、"hex"、"cbor"、"msgpack"、"cayenne_lpp"、"struct"，仅 serial_port、spi_sx1276、udp 支持
+    pub decoder: Option<String>,
+    // decoder 为 struct 时的结构体布局，对应 [[data_if.struct_field]]
+    pub struct_field: Option<Vec<StructFieldConfig>>,
+    // 串口参数
+    pub baud_rate: Option<u32>,
+    pub data_bits: Option<u8>,
+    // "none", "odd", "even"
+    pub parity: Option<String>,
+    pub stop_bits: Option<u8>,
+    // "none", "software", "hardware"
+    pub flow_control: Option<String>,
+    // 串口读超时，单位：毫秒
+    pub timeout_ms: Option<u64>,
+    // serial_port 是否启用 MIN 传输层（序号、ACK、重发），默认 false，需要设备端同时启用
+    pub min_transport: Option<bool>,
+    // serial_port 的 MIN ID 映射，对应 [[data_if.min_message]]
+    pub min_message: Option<Vec<MinMessageConfig>>,
+    // serial_line、stdin、fifo 的行分隔符，默认 "lf"
+    pub delimiter: Option<String>,
+    // serial_line 下行消息的结束符，默认 "lf"
+    pub terminator: Option<String>,
+    // serial_line、stdin、fifo 单行的最大长度，默认 1024
+    pub max_line_len: Option<usize>,
+    // modbus、sysfs 的轮询周期，单位：毫秒，modbus 默认 1000，sysfs 默认 10000
+    pub poll_interval_ms: Option<u64>,
+    // modbus 的响应超时，单位：毫秒，默认 1000
+    pub response_timeout_ms: Option<u64>,
+    // modbus 从站及寄存器映射，对应 [[data_if.device]]
+    pub device: Option<Vec<DeviceConfig>>,
+    // sysfs 的传感器，对应 [[data_if.sensor]]
+    pub sensor: Option<Vec<SensorConfig>>,
+    // socketcan 的接收过滤器，对应 [[data_if.can_filter]]
+    pub can_filter: Option<Vec<CanFilterConfig>>,
+    // socketcan 的报文及信号定义，对应 [[data_if.can_message]]
+    pub can_message: Option<Vec<CanMessageConfig>>,
+    // spi_sx1276 的射频参数，对应 [data_if.lora]
+    pub lora: Option<LoraConfig>,
+    // spi_sx1276 的 DIO0 引脚，配置后等待 GPIO 事件，否则轮询
+    pub dio0_chip: Option<String>,
+    pub dio0_line: Option<u32>,
+    // spi_sx1276 的复位引脚，配置后启动时复位射频模块
+    pub reset_chip: Option<String>,
+    pub reset_line: Option<u32>,
+}
+
+impl DataIfConfig {
+    pub fn serial_settings(&self) -> Result<SerialSettings, String> {
+        SerialSettings::new(self.baud_rate, self.data_bits, self.parity.as_deref(), self.stop_bits,
+            self.flow_control.as_deref(), self.timeout_ms)
+    }
+
+    // 已配置的接口类型相关字段，用于检查该 `if_type` 是否支持
+    pub fn set_keys(&self) -> Vec<&'static str> {
+        let keys = [
+            ("offset_file", self.offset_file.is_some()),
+            ("max_connections", self.max_connections.is_some()),
+            ("decoder", self.decoder.is_some()),
+            ("struct_field", self.struct_field.is_some()),
+            ("baud_rate", self.baud_rate.is_some()),
+            ("data_bits", self.data_bits.is_some()),
+            ("parity", self.parity.is_some()),
+            ("stop_bits", self.stop_bits.is_some()),
+            ("flow_control", self.flow_control.is_some()),
+            ("timeout_ms", self.timeout_ms.is_some()),
+            ("min_transport", self.min_transport.is_some()),
+            ("min_message", self.min_message.is_some()),
+            ("delimiter", self.delimiter.is_some()),
+            ("terminator", self.terminator.is_some()),
+            ("max_line_len", self.max_line_len.is_some()),
+            ("poll_interval_ms", self.poll_interval_ms.is_some()),
+            ("response_timeout_ms", self.response_timeout_ms.is_some()),
+            ("device", self.device.is_some()),
+            ("sensor", self.sensor.is_some()),
+            ("can_filter", self.can_filter.is_some()),
+            ("can_message", self.can_message.is_some()),
+            ("lora", self.lora.is_some()),
+            ("dio0_chip", self.dio0_chip.is_some()),
+            ("dio0_line", self.dio0_line.is_some()),
+            ("reset_chip", self.reset_chip.is_some()),
+            ("reset_line", self.reset_line.is_some()),
+        ];
+        keys.iter().filter(|(_, set)| *set).map(|(key, _)| *key).collect()
+    }
 }
\ No newline at end of file